@@ -0,0 +1,480 @@
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use crate::deck::{Cloud, Envelope};
+use crate::error::WaveJumperError;
+use crate::fx::{FilterMode, FxParams, MAX_DELAY_MS};
+use crate::modulate::ModRoute;
+use crate::patch::ChannelMap;
+use crate::route::MAX_OUTPUTS;
+use crate::settings::{Settings, MAX_RATCHETS, SPEED_RANGE};
+
+// Where we look for the config, next to ./assets/
+pub const CONFIG_PATH: &str = "./wave_jumper.conf";
+
+// When a skip takes effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipOn {
+    // Keep time and change tapes on the next step
+    Step,
+    // Change tapes the moment the button goes
+    Press,
+}
+
+// What the encoder turns when the menu is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Knob {
+    Volume,
+    Tempo,
+    Tape,
+}
+
+impl Knob {
+    pub fn next(self) -> Self {
+        match self {
+            Knob::Volume => Knob::Tempo,
+            Knob::Tempo => Knob::Tape,
+            Knob::Tape => Knob::Volume,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Knob::Volume => "volume",
+            Knob::Tempo => "tempo",
+            Knob::Tape => "tape",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Knob::Volume, Knob::Tempo, Knob::Tape].into_iter().find(|k| k.name() == name)
+    }
+}
+
+// How the instrument is wired up and how it should feel.
+// Read from `key = value` lines, anything left out keeps its default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub button_pin: Option<u8>,
+    pub encoder_a_pin: Option<u8>,
+    pub encoder_b_pin: Option<u8>,
+    pub encoder_button_pin: Option<u8>,
+    // Quadrature edges between the encoder's clicks
+    pub encoder_steps: u32,
+    // What turning the encoder does outside the menu
+    pub encoder_knob: Knob,
+    pub debounce: Duration,
+    pub long_press: Duration,
+    pub double_gap: Duration,
+    // How often the input thread looks at the panel
+    pub input_poll: Duration,
+    pub skip_on: SkipOn,
+    // How long a mux channel gets to settle before we read it
+    pub mux_settle: Duration,
+    // Reads of each mux channel per scan, the majority wins
+    pub mux_samples: u32,
+    // Scans a wire has to read the same before we believe it was plugged or pulled
+    pub wire_hysteresis: u32,
+    // Which chip line each hole is wired to, written by --calibrate
+    pub channel_map: ChannelMap,
+    // Steps to play backwards, one bit each
+    pub reversed_steps: u16,
+    // How fast the tape runs to start with, overall and step by step
+    pub speed: f32,
+    pub stretch: bool,
+    pub step_speeds: [f32; 16],
+    // Grains instead of the tape played straight, and what they're like
+    pub granular: bool,
+    pub cloud: Cloud,
+    // Each step's ratchets, stutter and chance of either
+    pub ratchets: [u32; 16],
+    pub stutters_ms: [u32; 16],
+    pub repeat_chance: [u32; 16],
+    // The hole a wire goes into to ratchet its step, and how many times
+    pub ratchet_hole: Option<u32>,
+    pub hole_ratchets: u32,
+    // The effects, and a route from the patch into them for every `mod` line
+    pub fx: FxParams,
+    pub mods: Vec<ModRoute>,
+    // Where each step sounds, and out of how many outputs
+    pub step_pans: [f32; 16],
+    pub width: f32,
+    pub outputs: u16,
+    pub step_outputs: [u16; 16],
+    // Where to listen for OSC, if at all
+    pub osc_port: Option<u16>,
+    // The MIDI input to listen to, by any part of its name
+    pub midi_in: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            button_pin: Some(26),
+            encoder_a_pin: None,
+            encoder_b_pin: None,
+            encoder_button_pin: None,
+            encoder_steps: 4,
+            encoder_knob: Knob::Volume,
+            debounce: Duration::from_millis(10),
+            long_press: Duration::from_millis(600),
+            double_gap: Duration::from_millis(300),
+            input_poll: Duration::from_millis(2),
+            skip_on: SkipOn::Step,
+            mux_settle: Duration::from_micros(1000),
+            mux_samples: 3,
+            wire_hysteresis: 2,
+            channel_map: ChannelMap::default(),
+            reversed_steps: 0,
+            speed: 1.0,
+            stretch: false,
+            step_speeds: [1.0; 16],
+            granular: false,
+            cloud: Cloud::default(),
+            ratchets: [1; 16],
+            stutters_ms: [0; 16],
+            repeat_chance: [100; 16],
+            ratchet_hole: None,
+            hole_ratchets: 2,
+            fx: FxParams::default(),
+            mods: Vec::new(),
+            step_pans: [0.0; 16],
+            width: 1.0,
+            outputs: 2,
+            step_outputs: [0; 16],
+            osc_port: None,
+            midi_in: None,
+        }
+    }
+}
+
+fn parse_pin(value: &str) -> Result<Option<u8>, String> {
+    match value {
+        "none" => Ok(None),
+        _ => value.parse::<u8>().map(Some).map_err(|_| format!("{} isn't a gpio pin", value)),
+    }
+}
+
+fn parse_num(value: &str) -> Result<u32, String> {
+    value.parse::<u32>().map_err(|_| format!("{} isn't a number", value))
+}
+
+fn parse_ms(value: &str) -> Result<Duration, String> {
+    parse_num(value).map(|ms| Duration::from_millis(ms as u64))
+}
+
+fn parse_us(value: &str) -> Result<Duration, String> {
+    parse_num(value).map(|us| Duration::from_micros(us as u64))
+}
+
+// Sixteen line numbers, every one of 0 to 15 once
+fn parse_map(value: &str) -> Result<[u8; 16], String> {
+    let lines: Vec<u8> = value.split_whitespace()
+        .map(|v| v.parse::<u8>().map_err(|_| format!("{} isn't a chip line", v)))
+        .collect::<Result<_, _>>()?;
+    let map: [u8; 16] = lines.try_into().map_err(|_| "a map needs a line for all 16 holes".to_string())?;
+    match (0..16).find(|line| !map.contains(line)) {
+        Some(missing) => Err(format!("line {} is missing from the map", missing)),
+        None => Ok(map),
+    }
+}
+
+// Any of the steps, by number
+fn parse_steps(value: &str) -> Result<u16, String> {
+    value.split_whitespace().try_fold(0u16, |steps, v| match v.parse::<u32>() {
+        Ok(step) if step < 16 => Ok(steps | 1 << step),
+        _ => Err(format!("{} isn't a step", v)),
+    })
+}
+
+fn parse_speed(value: &str) -> Result<f32, String> {
+    let (lo, hi) = SPEED_RANGE;
+    match value.parse::<f32>() {
+        Ok(speed) if (lo..=hi).contains(&speed) => Ok(speed),
+        _ => Err(format!("{} isn't a speed from {} to {}", value, lo, hi)),
+    }
+}
+
+fn parse_pan(value: &str) -> Result<f32, String> {
+    value.parse::<f32>().ok().filter(|v| (-1.0..=1.0).contains(v))
+        .ok_or_else(|| format!("{} isn't a pan from -1 to 1", value))
+}
+
+fn parse_output(value: &str) -> Result<u16, String> {
+    parse_num(value).ok().filter(|&o| o < MAX_OUTPUTS as u32).map(|o| o as u16)
+        .ok_or_else(|| format!("{} isn't an output from 0 to {}", value, MAX_OUTPUTS - 1))
+}
+
+fn parse_ratchets(value: &str) -> Result<u32, String> {
+    match parse_num(value)? {
+        times @ 1..=MAX_RATCHETS => Ok(times),
+        _ => Err(format!("a step ratchets 1 to {} times, not {}", MAX_RATCHETS, value)),
+    }
+}
+
+// Anything from 0 to 1, a mix or a knob
+fn parse_unit(value: &str) -> Result<f32, String> {
+    value.parse::<f32>().ok().filter(|v| (0.0..=1.0).contains(v))
+        .ok_or_else(|| format!("{} isn't from 0 to 1", value))
+}
+
+fn parse_percent(value: &str) -> Result<u32, String> {
+    parse_num(value).and_then(|p| if p <= 100 { Ok(p) } else { Err(format!("{} isn't a percentage", value)) })
+}
+
+// A value for each step from the first, any left out keep `default`
+fn parse_per_step<T: Copy>(value: &str, default: T, parse: fn(&str) -> Result<T, String>) -> Result<[T; 16], String> {
+    let mut steps = [default; 16];
+    for (k, v) in value.split_whitespace().enumerate() {
+        *steps.get_mut(k).ok_or("there are only 16 steps")? = parse(v)?;
+    }
+    Ok(steps)
+}
+
+fn parse_switch(value: &str) -> Result<bool, String> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("expected `on` or `off`, got `{}`", value)),
+    }
+}
+
+fn show_map(map: &[u8; 16]) -> String {
+    map.iter().map(|line| line.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+impl Config {
+    // A missing file is fine, that's just the defaults
+    pub fn load(path: &str) -> Result<Self, WaveJumperError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(WaveJumperError::Config(format!("{} can't be read: {}", path, e))),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, WaveJumperError> {
+        let mut config = Config::default();
+        for (k, line) in text.lines().enumerate() {
+            let line: &str = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = |why: String| WaveJumperError::Config(format!("line {}: {}", k + 1, why));
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| bad_line(format!("expected `key = value`, got `{}`", line)))?;
+            config.set(key.trim(), value.trim()).map_err(bad_line)?;
+        }
+        Ok(config)
+    }
+
+    // Write a calibrated channel map over any old one, leaving the rest of the file be
+    pub fn save_channel_map(path: &str, map: &ChannelMap) -> Result<(), WaveJumperError> {
+        let old: String = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(WaveJumperError::Config(format!("{} can't be read: {}", path, e))),
+        };
+        let is_map = |line: &&str| {
+            let key: &str = line.split('=').next().unwrap_or("").trim();
+            key == "demux_map" || key == "mux_map"
+        };
+        let mut lines: Vec<String> = old.lines().filter(|l| !is_map(l)).map(String::from).collect();
+        lines.push(format!("demux_map = {}", show_map(&map.demux)));
+        lines.push(format!("mux_map = {}", show_map(&map.mux)));
+        fs::write(path, lines.join("\n") + "\n")
+            .map_err(|e| WaveJumperError::Config(format!("{} can't be written: {}", path, e)))
+    }
+
+    // What the performer starts out with
+    pub fn settings(&self) -> Settings {
+        Settings {
+            reversed_steps: self.reversed_steps,
+            speed: self.speed,
+            stretch: self.stretch,
+            step_speeds: self.step_speeds,
+            granular: self.granular,
+            cloud: self.cloud,
+            ratchets: self.ratchets,
+            stutters_ms: self.stutters_ms,
+            repeat_chance: self.repeat_chance,
+            ratchet_hole: self.ratchet_hole,
+            hole_ratchets: self.hole_ratchets,
+            fx: self.fx,
+            mods: self.mods.clone(),
+            step_pans: self.step_pans,
+            width: self.width,
+            outputs: self.outputs,
+            step_outputs: self.step_outputs,
+            ..Settings::default()
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "button_pin" => self.button_pin = parse_pin(value)?,
+            "encoder_a_pin" => self.encoder_a_pin = parse_pin(value)?,
+            "encoder_b_pin" => self.encoder_b_pin = parse_pin(value)?,
+            "encoder_button_pin" => self.encoder_button_pin = parse_pin(value)?,
+            "encoder_steps" => self.encoder_steps = parse_num(value)?.max(1),
+            "encoder_knob" => self.encoder_knob = Knob::from_name(value)
+                .ok_or_else(|| format!("{} isn't something the encoder can turn", value))?,
+            "debounce_ms" => self.debounce = parse_ms(value)?,
+            "long_press_ms" => self.long_press = parse_ms(value)?,
+            "double_gap_ms" => self.double_gap = parse_ms(value)?,
+            "input_poll_ms" => self.input_poll = parse_ms(value)?.max(Duration::from_millis(1)),
+            "skip_on" => self.skip_on = match value {
+                "step" => SkipOn::Step,
+                "press" => SkipOn::Press,
+                _ => return Err(format!("skip_on is `step` or `press`, not `{}`", value)),
+            },
+            "mux_settle_us" => self.mux_settle = parse_us(value)?,
+            "mux_samples" => self.mux_samples = parse_num(value)?.max(1),
+            "wire_hysteresis" => self.wire_hysteresis = parse_num(value)?.max(1),
+            "demux_map" => self.channel_map.demux = parse_map(value)?,
+            "mux_map" => self.channel_map.mux = parse_map(value)?,
+            "reversed_steps" => self.reversed_steps = parse_steps(value)?,
+            "speed" => self.speed = parse_speed(value)?,
+            "stretch" => self.stretch = parse_switch(value)?,
+            "step_speeds" => self.step_speeds = parse_per_step(value, 1.0, parse_speed)?,
+            "granular" => self.granular = parse_switch(value)?,
+            "grain_ms" => self.cloud.grain_ms = parse_num(value)?.clamp(1, 1000),
+            "grain_density" => self.cloud.density = parse_num(value)?.clamp(1, 1000),
+            "grain_spray_ms" => self.cloud.spray_ms = parse_num(value)?.min(5000),
+            "ratchets" => self.ratchets = parse_per_step(value, 1, parse_ratchets)?,
+            "stutters_ms" => self.stutters_ms = parse_per_step(value, 0, parse_num)?,
+            "repeat_chance" => self.repeat_chance = parse_per_step(value, 100, parse_percent)?,
+            "ratchet_hole" => self.ratchet_hole = match value {
+                "none" => None,
+                _ => Some(parse_num(value).and_then(|h| if h < 16 { Ok(h) } else { Err(format!("{} isn't a hole", value)) })?),
+            },
+            "hole_ratchets" => self.hole_ratchets = parse_ratchets(value)?,
+            "filter" => self.fx.filter = FilterMode::from_name(value)
+                .ok_or_else(|| format!("{} isn't a filter, try off, low, high or band", value))?,
+            "cutoff_hz" => self.fx.cutoff_hz = parse_num(value)?.clamp(20, 20_000) as f32,
+            "resonance" => self.fx.resonance = parse_unit(value)?,
+            "crush_bits" => self.fx.bits = parse_num(value)?.clamp(2, 16),
+            "downsample" => self.fx.downsample = parse_num(value)?.clamp(1, 32),
+            "delay_ms" => self.fx.delay_ms = parse_num(value)?.clamp(1, MAX_DELAY_MS),
+            "delay_feedback" => self.fx.feedback = parse_unit(value)?.min(0.95),
+            "delay_mix" => self.fx.delay_mix = parse_unit(value)?,
+            "reverb_room" => self.fx.room = parse_unit(value)?,
+            "reverb_mix" => self.fx.reverb_mix = parse_unit(value)?,
+            "limit" => self.fx.ceiling = parse_unit(value)?.max(0.01),
+            // Every line adds another
+            "mod" => self.mods.push(ModRoute::parse(value)?),
+            "step_pans" => self.step_pans = parse_per_step(value, 0.0, parse_pan)?,
+            "width" => self.width = value.parse::<f32>().ok().filter(|w| (0.0..=2.0).contains(w))
+                .ok_or_else(|| format!("{} isn't a width from 0 to 2", value))?,
+            "outputs" => self.outputs = parse_num(value).ok().filter(|o| (1..=MAX_OUTPUTS as u32).contains(o)).map(|o| o as u16)
+                .ok_or_else(|| format!("{} isn't from 1 to {} outputs", value, MAX_OUTPUTS))?,
+            "step_outputs" => self.step_outputs = parse_per_step(value, 0, parse_output)?,
+            "grain_envelope" => self.cloud.envelope = Envelope::from_name(value)
+                .ok_or_else(|| format!("{} isn't an envelope, try hann, tri or perc", value))?,
+            "osc_port" => self.osc_port = match value {
+                "none" => None,
+                _ => Some(value.parse::<u16>().map_err(|_| format!("{} isn't a port", value))?),
+            },
+            "midi_in" => self.midi_in = (value != "none").then(|| value.to_string()),
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_what_is_there_and_defaults_the_rest() {
+        let text = "
+            # the knob on the front
+            encoder_a_pin = 12
+            encoder_b_pin = 13   # wired backwards? swap these
+            encoder_knob = tempo
+            button_pin = none
+            skip_on = press
+            reversed_steps = 3 12
+            step_speeds = 1 0.5 2
+            stretch = on
+            osc_port = 9000
+            midi_in = nanoKONTROL
+            granular = on
+            grain_envelope = perc
+            grain_density = 0
+            ratchets = 1 3
+            repeat_chance = 100 50
+            ratchet_hole = 15
+            filter = low
+            delay_mix = 0.3
+            mod = wires cutoff 0.5
+            mod = distance reverb_mix
+            step_pans = -1 1 -0.5
+            outputs = 8
+            step_outputs = 0 2 4 6
+        ";
+        let config = Config::parse(text).unwrap();
+        assert_eq!(config.encoder_a_pin, Some(12));
+        assert_eq!(config.encoder_b_pin, Some(13));
+        assert_eq!(config.encoder_knob, Knob::Tempo);
+        assert_eq!(config.button_pin, None);
+        assert_eq!(config.skip_on, SkipOn::Press);
+        assert_eq!(config.reversed_steps, (1 << 3) | (1 << 12));
+        assert_eq!(config.settings().step_speeds[..4], [1.0, 0.5, 2.0, 1.0]);
+        assert!(config.settings().stretch);
+        assert_eq!(config.osc_port, Some(9000));
+        assert_eq!(config.midi_in.as_deref(), Some("nanoKONTROL"));
+        assert!(config.settings().granular);
+        assert_eq!(config.settings().ratchets[..3], [1, 3, 1]);
+        assert_eq!(config.repeat_chance[..3], [100, 50, 100]);
+        assert_eq!(config.settings().ratchet_hole, Some(15));
+        assert_eq!((config.fx.filter, config.fx.delay_mix), (FilterMode::LowPass, 0.3));
+        assert_eq!(config.settings().mods.len(), 2);
+        assert_eq!(config.settings().step_pans[..4], [-1.0, 1.0, -0.5, 0.0]);
+        assert_eq!((config.settings().outputs, config.step_outputs[3]), (8, 6));
+        assert_eq!(Config::parse("outputs = 32").unwrap().outputs, 32);
+        assert_eq!(Config::parse("outputs = 1").unwrap().outputs, 1);
+        assert_eq!(Config::parse("step_outputs = 31").unwrap().step_outputs[0], 31);
+        assert_eq!(config.cloud, Cloud { envelope: Envelope::Perc, density: 1, ..Cloud::default() });
+        assert_eq!(config.long_press, Config::default().long_press);
+    }
+
+    #[test]
+    fn complains_with_a_line_number() {
+        let err = Config::parse("debounce_ms = 5\nbutton_pin = 300\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(Config::parse("volume 11").is_err());
+        assert!(Config::parse("colour = red").is_err());
+        assert!(Config::parse("mux_map = 0 1 2").is_err());
+        assert!(Config::parse("reversed_steps = 4 16").is_err());
+        assert!(Config::parse("speed = 9").is_err());
+        assert!(Config::parse("grain_envelope = blob").is_err());
+        assert!(Config::parse("ratchets = 1 9").is_err());
+        assert!(Config::parse("repeat_chance = 101").is_err());
+        assert!(Config::parse("ratchet_hole = 16").is_err());
+        assert!(Config::parse("delay_mix = 2").is_err());
+        assert!(Config::parse("mod = wires colour").is_err());
+        assert!(Config::parse("step_pans = 0 2").is_err());
+        assert!(Config::parse("step_outputs = 32").is_err());
+        assert!(Config::parse("outputs = 0").is_err());
+        assert!(Config::parse("outputs = 33").is_err());
+        assert!(Config::parse(&format!("step_speeds = {}", ["1"; 17].join(" "))).is_err());
+        assert!(Config::parse("mux_map = 0 0 2 3 4 5 6 7 8 9 10 11 12 13 14 15").is_err());
+    }
+
+    #[test]
+    fn channel_map_survives_a_round_trip() {
+        let path = std::env::temp_dir().join(format!("wave_jumper_{}.conf", std::process::id()));
+        let path: &str = path.to_str().unwrap();
+        fs::write(path, "encoder_knob = tape\nmux_map = 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15\n").unwrap();
+        let mut map = ChannelMap::default();
+        map.mux.swap(1, 2);
+        Config::save_channel_map(path, &map).unwrap();
+        let config = Config::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(config.channel_map, map);
+        assert_eq!(config.encoder_knob, Knob::Tape);
+    }
+}
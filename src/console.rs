@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// How many lines we hang on to while something else owns the terminal
+const CAPTURE_LINES: usize = 64;
+
+// None while stdout is ours to print to
+static CAPTURED: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+
+// Feedback on the console, or into the capture while a terminal UI is drawing
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => { $crate::console::emit(format!($($arg)*), false) };
+}
+
+// Same again but for stderr
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::console::emit(format!($($arg)*), true) };
+}
+
+pub fn emit(line: String, is_err: bool) {
+    let mut captured = CAPTURED.lock().unwrap_or_else(|e| e.into_inner());
+    match captured.as_mut() {
+        Some(lines) => {
+            if lines.len() == CAPTURE_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+        None if is_err => eprintln!("{}", line),
+        None => println!("{}", line),
+    }
+}
+
+// Stop printing and start collecting lines for a terminal UI to show
+pub fn capture() {
+    let mut captured = CAPTURED.lock().unwrap_or_else(|e| e.into_inner());
+    captured.get_or_insert_with(VecDeque::new);
+}
+
+// Hand stdout back, printing whatever we collected in the meantime
+pub fn release() {
+    let lines = CAPTURED.lock().unwrap_or_else(|e| e.into_inner()).take();
+    for line in lines.into_iter().flatten() {
+        println!("{}", line);
+    }
+}
+
+// The last `n` captured lines, oldest first
+pub fn recent(n: usize) -> Vec<String> {
+    let captured = CAPTURED.lock().unwrap_or_else(|e| e.into_inner());
+    captured.as_ref().map_or(Vec::new(), |lines| {
+        lines.iter().skip(lines.len().saturating_sub(n)).cloned().collect()
+    })
+}
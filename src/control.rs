@@ -0,0 +1,96 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::fx::FxParam;
+use crate::input::InputEvent;
+use crate::jump::JumpStrategy;
+
+// Things the outside world can ask of a running instrument
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Skip,
+    TogglePause,
+    NextStrategy,
+    // Turn the volume up or down by this much
+    Volume(f32),
+    // Or set it outright
+    SetVolume(f32),
+    // How fast the tape runs, overall or for one step
+    Speed(f32),
+    StepSpeed(u32, f32),
+    Stretch(bool),
+    // Turn an effect's knob, from 0 to 1
+    Fx(FxParam, f32),
+    Quit,
+    // Something happened on the panel
+    Input(InputEvent),
+}
+
+// What the instrument is up to, published once a step
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub tape: String,
+    pub tape_ms: u64,
+    pub buffer_ms: u64,
+    pub chunk_len: u64,
+    pub position: u32,
+    pub mux_word: u16,
+    // The last word we read from every step, these are our jumper wires
+    pub patch: [u16; 16],
+    // Wires that come and go, and how sure we are of every hole to hole connection in percent
+    pub flaky: [u16; 16],
+    pub confidence: [[u8; 16]; 16],
+    pub strategy: JumpStrategy,
+    pub volume: f32,
+    pub paused: bool,
+}
+
+impl Status {
+    // One step is a sixteenth note
+    pub fn bpm(&self) -> u64 {
+        15_000 / self.chunk_len.max(1)
+    }
+}
+
+// The instrument's end of the line to any controllers
+pub struct Remote {
+    commands: Receiver<Command>,
+    sender: Sender<Command>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        Remote::new()
+    }
+}
+
+impl Remote {
+    pub fn new() -> Self {
+        let (sender, commands) = channel();
+        Remote { commands, sender, status: Arc::new(Mutex::new(Status::default())) }
+    }
+
+    // A handle for a controller to send us commands on
+    pub fn sender(&self) -> Sender<Command> {
+        self.sender.clone()
+    }
+
+    // A handle for a controller to watch what we're doing
+    pub fn status(&self) -> Arc<Mutex<Status>> {
+        Arc::clone(&self.status)
+    }
+
+    // The next order, if one turns up in time
+    pub fn wait(&self, timeout: Duration) -> Option<Command> {
+        match self.commands.recv_timeout(timeout) {
+            Ok(cmd) => Some(cmd),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    pub fn publish(&self, status: &Status) {
+        *self.status.lock().unwrap_or_else(|e| e.into_inner()) = status.clone();
+    }
+}
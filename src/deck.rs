@@ -0,0 +1,704 @@
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rodio::source::SeekError;
+use rodio::Source;
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tape::Tape;
+
+// Nothing asked of the deck
+const NO_SEEK: u64 = u64::MAX;
+// Set on a seek to play the tape backwards from there
+const BACKWARDS: u64 = 1 << 63;
+// Which tape a seek lands on, one up so nothing means the tape we're on
+const REEL_SHIFT: u32 = 56;
+const REEL_MASK: u64 = 0x7f << REEL_SHIFT;
+// How long each grain of a time stretch lasts
+const GRAIN_MS: u64 = 40;
+// Most grains a cloud can sound at once, any more wait their turn
+const MAX_GRAINS: usize = 64;
+// Set on a packed cloud, so no cloud at all is nothing
+const CLOUD_ON: u64 = 1 << 63;
+
+// The shape of a grain, from start to end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Envelope {
+    // Smooth in and out
+    #[default]
+    Hann,
+    Triangle,
+    // A quick start and a long tail
+    Perc,
+}
+
+impl Envelope {
+    pub const ALL: [Envelope; 3] = [Envelope::Hann, Envelope::Triangle, Envelope::Perc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Envelope::Hann => "hann",
+            Envelope::Triangle => "tri",
+            Envelope::Perc => "perc",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Envelope::ALL.into_iter().find(|e| e.name() == name)
+    }
+
+    // How loud a grain is `t` of the way through
+    fn gain(self, t: f32) -> f32 {
+        match self {
+            Envelope::Hann => 0.5 - 0.5 * (t * std::f32::consts::TAU).cos(),
+            Envelope::Triangle => triangle_at(t),
+            Envelope::Perc => (t * 50.0).min(1.0) * (1.0 - t).powi(2),
+        }
+    }
+}
+
+// A granular read head: rather than play the tape straight, scatter grains
+// about the head as it goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cloud {
+    pub grain_ms: u32,
+    // Grains started every second
+    pub density: u32,
+    // How far either side of the head a grain can start
+    pub spray_ms: u32,
+    pub envelope: Envelope,
+}
+
+impl Default for Cloud {
+    fn default() -> Self {
+        Cloud { grain_ms: 80, density: 40, spray_ms: 30, envelope: Envelope::default() }
+    }
+}
+
+impl Cloud {
+    // All of it in one word, so the audio thread never sees half a change
+    fn pack(cloud: Option<Cloud>) -> u64 {
+        cloud.map_or(0, |c| {
+            let envelope: u64 = Envelope::ALL.iter().position(|&e| e == c.envelope).unwrap_or(0) as u64;
+            CLOUD_ON | envelope << 48 | (c.spray_ms.min(0xffff) as u64) << 32
+                | (c.density.clamp(1, 0xffff) as u64) << 16 | c.grain_ms.clamp(1, 0xffff) as u64
+        })
+    }
+
+    fn unpack(word: u64) -> Option<Cloud> {
+        (word & CLOUD_ON != 0).then(|| Cloud {
+            grain_ms: (word & 0xffff) as u32,
+            density: (word >> 16 & 0xffff) as u32,
+            spray_ms: (word >> 32 & 0xffff) as u32,
+            envelope: Envelope::ALL.get((word >> 48 & 0xff) as usize).copied().unwrap_or_default(),
+        })
+    }
+}
+
+// The instrument's hand on the deck, shared with the audio thread
+#[derive(Debug)]
+pub struct Transport {
+    seek_ms: AtomicU64,
+    crossfade_ms: AtomicU32,
+    position_ms: AtomicU64,
+    // How fast the head runs over the tape, as the bits of an f32
+    speed: AtomicU32,
+    // Keep the tape's own pitch whatever the speed
+    stretch: AtomicBool,
+    // A packed Cloud, if we're playing grains
+    cloud: AtomicU64,
+    // How often to go back to where a seek landed, 0 for never
+    repeat_ms: AtomicU32,
+}
+
+impl Transport {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Transport {
+            seek_ms: AtomicU64::new(NO_SEEK),
+            crossfade_ms: AtomicU32::new(0),
+            position_ms: AtomicU64::new(0),
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            stretch: AtomicBool::new(false),
+            cloud: AtomicU64::new(0),
+            repeat_ms: AtomicU32::new(0),
+        })
+    }
+
+    fn store_seek(&self, tape: Option<usize>, ms: u64, backwards: bool) {
+        let reel: u64 = tape.map_or(0, |t| (t as u64 + 1).min(0x7f) << REEL_SHIFT);
+        let word: u64 = ms & !(BACKWARDS | REEL_MASK) | reel;
+        self.seek_ms.store(if backwards { word | BACKWARDS } else { word }, Ordering::Relaxed);
+    }
+
+    // Jump on the next frame, fading over the crossfade time
+    pub fn seek(&self, ms: u64) {
+        self.store_seek(None, ms, false);
+    }
+
+    // Jump the same, but play backwards from just before `ms`
+    pub fn seek_back(&self, ms: u64) {
+        self.store_seek(None, ms, true);
+    }
+
+    // Jump over to another of the deck's tapes, whichever way
+    pub fn seek_tape(&self, tape: usize, ms: u64, backwards: bool) {
+        self.store_seek(Some(tape), ms, backwards);
+    }
+
+    pub fn set_crossfade(&self, ms: u32) {
+        self.crossfade_ms.store(ms, Ordering::Relaxed);
+    }
+
+    // Run the tape faster or slower from the next frame, pitch and all unless we stretch it
+    pub fn set_speed(&self, speed: f32, stretch: bool) {
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+        self.stretch.store(stretch, Ordering::Relaxed);
+    }
+
+    // Play grains about the head from the next frame, or the tape straight with None
+    pub fn set_cloud(&self, cloud: Option<Cloud>) {
+        self.cloud.store(Cloud::pack(cloud), Ordering::Relaxed);
+    }
+
+    // Go back to where the next seek lands every `every_ms`, over and over, or 0 for never
+    pub fn set_repeat(&self, every_ms: u32) {
+        self.repeat_ms.store(every_ms, Ordering::Relaxed);
+    }
+
+    // Where the deck's head was on the tape, last we heard
+    pub fn position_ms(&self) -> u64 {
+        self.position_ms.load(Ordering::Relaxed)
+    }
+
+    // Which tape, where on it and whether to play backwards from there, in the one go
+    fn take_seek(&self) -> Option<(Option<usize>, u64, bool)> {
+        match self.seek_ms.swap(NO_SEEK, Ordering::Relaxed) {
+            NO_SEEK => None,
+            word => {
+                let reel: u64 = (word & REEL_MASK) >> REEL_SHIFT;
+                Some((reel.checked_sub(1).map(|r| r as usize), word & !(BACKWARDS | REEL_MASK), word & BACKWARDS != 0))
+            }
+        }
+    }
+}
+
+// The frame after `frame`, whichever way the tape is running
+fn next_frame(frame: usize, frames: usize, backwards: bool) -> usize {
+    if backwards { (frame + frames - 1) % frames } else { (frame + 1) % frames }
+}
+
+// How loud a grain is `t` of the way through, nothing at either end and all of it in the middle
+fn triangle_at(t: f32) -> f32 {
+    1.0 - (2.0 * t - 1.0).abs()
+}
+
+fn triangle(age: usize, len: usize) -> f32 {
+    triangle_at(age as f32 / len as f32)
+}
+
+// One of the tapes on the deck
+struct Reel {
+    samples: Arc<[f32]>,
+    channels: usize,
+    rate: u32,
+    frames: usize,
+    // Frames of this tape to each frame of the deck, so every tape plays at its own pitch
+    pace: f32,
+}
+
+impl Reel {
+    // A mono tape plays on every channel, and the deck takes what it has room for of the rest
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        self.samples[frame * self.channels + channel % self.channels]
+    }
+}
+
+// Somewhere on one of the tapes, part way to the next frame
+#[derive(Debug, Clone, Copy)]
+struct Head {
+    reel: usize,
+    frame: usize,
+    phase: f32,
+    backwards: bool,
+}
+
+impl Head {
+    fn at(reel: usize, frame: usize, backwards: bool) -> Self {
+        Head { reel, frame, phase: 0.0, backwards }
+    }
+
+    // Move `by` of the deck's frames along, however many of the tape's that comes to
+    fn advance(&mut self, by: f32, reels: &[Reel]) {
+        let reel: &Reel = &reels[self.reel];
+        self.phase += by * reel.pace;
+        let whole: f32 = self.phase.floor();
+        self.phase -= whole;
+        let n: usize = whole as usize % reel.frames;
+        self.frame = if self.backwards { (self.frame + reel.frames - n) % reel.frames } else { (self.frame + n) % reel.frames };
+    }
+
+    // Between this frame and the next, linearly
+    fn read(&self, reels: &[Reel], channel: usize) -> f32 {
+        let reel: &Reel = &reels[self.reel];
+        let next: usize = next_frame(self.frame, reel.frames, self.backwards);
+        let here: f32 = reel.sample(self.frame, channel);
+        here + (reel.sample(next, channel) - here) * self.phase
+    }
+}
+
+// Whatever reads the tape. The head runs at any speed, and for a time stretch
+// two grains replay the tape at its own pitch from wherever the head was.
+#[derive(Debug, Clone, Copy)]
+struct Voice {
+    head: Head,
+    grains: [Head; 2],
+    age: usize,
+}
+
+impl Voice {
+    fn at(head: Head) -> Self {
+        Voice { head, grains: [head; 2], age: 0 }
+    }
+
+    fn read(&self, reels: &[Reel], channel: usize, grain_len: Option<usize>) -> f32 {
+        match grain_len {
+            None => self.head.read(reels, channel),
+            // Half a grain apart the two triangles always add up to one
+            Some(len) => {
+                let ages: [usize; 2] = [self.age, (self.age + len / 2) % len];
+                ages.iter().zip(self.grains)
+                    .map(|(&age, grain)| triangle(age, len) * grain.read(reels, channel))
+                    .sum()
+            }
+        }
+    }
+
+    fn advance(&mut self, speed: f32, reels: &[Reel], grain_len: Option<usize>) {
+        self.head.advance(speed, reels);
+        let Some(len) = grain_len else {
+            // Ready to go whenever a stretch starts
+            self.grains = [self.head; 2];
+            return;
+        };
+        for grain in self.grains.iter_mut() {
+            grain.advance(1.0, reels);
+        }
+        // A grain starts over from the head once it's faded right out
+        self.age = (self.age + 1) % len;
+        if self.age == 0 {
+            self.grains[0] = self.head;
+        } else if self.age == len / 2 {
+            self.grains[1] = self.head;
+        }
+    }
+}
+
+// One grain of a cloud
+#[derive(Debug, Clone, Copy)]
+struct Grain {
+    head: Head,
+    age: usize,
+    len: usize,
+}
+
+// The old voice, still sounding while the new one fades in
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    voice: Voice,
+    left: usize,
+    len: usize,
+}
+
+// Looping tapes held in memory, jumping about wherever the transport says.
+// The first tape sets the channels and rate for the lot.
+pub struct Deck {
+    reels: Vec<Reel>,
+    channels: u16,
+    rate: u32,
+    voice: Voice,
+    // The channel of the frame we're on
+    channel: usize,
+    fade: Option<Fade>,
+    // What the transport asked for at the top of this frame
+    speed: f32,
+    grain_len: Option<usize>,
+    cloud: Option<Cloud>,
+    grains: Vec<Grain>,
+    // Frames until the cloud's next grain
+    until_grain: f32,
+    rng: SmallRng,
+    // Where the last seek landed, and the frames until we go back there if we're repeating
+    mark: Voice,
+    repeat_every: usize,
+    until_repeat: usize,
+    transport: Arc<Transport>,
+}
+
+impl Deck {
+    pub fn new(tape: &Tape, transport: Arc<Transport>) -> Self {
+        Deck::with_tapes(std::slice::from_ref(tape), transport)
+    }
+
+    // Any of `tapes` can be sought to, starting at the top of the first
+    pub fn with_tapes(tapes: &[Tape], transport: Arc<Transport>) -> Self {
+        transport.seek_ms.store(NO_SEEK, Ordering::Relaxed);
+        transport.position_ms.store(0, Ordering::Relaxed);
+        let channels: u16 = tapes.first().map_or(1, |t| t.channels.max(1));
+        let rate: u32 = tapes.first().map_or(1, |t| t.rate.max(1));
+        let reels: Vec<Reel> = tapes.iter()
+            .map(|t| Reel {
+                samples: Arc::clone(&t.samples),
+                channels: t.channels.max(1) as usize,
+                rate: t.rate.max(1),
+                frames: t.frames(),
+                pace: t.rate.max(1) as f32 / rate as f32,
+            })
+            .collect();
+        // One silent tape and there's nothing we can play
+        let reels: Vec<Reel> = if reels.iter().all(|r| r.frames > 0) { reels } else { Vec::new() };
+        Deck {
+            reels,
+            channels,
+            rate,
+            voice: Voice::at(Head::at(0, 0, false)),
+            channel: 0,
+            fade: None,
+            speed: 1.0,
+            grain_len: None,
+            cloud: None,
+            grains: Vec::with_capacity(MAX_GRAINS),
+            until_grain: 0.0,
+            rng: SmallRng::from_rng(&mut rand::rng()),
+            mark: Voice::at(Head::at(0, 0, false)),
+            repeat_every: 0,
+            until_repeat: 0,
+            transport,
+        }
+    }
+
+    fn ms_to_frame(&self, reel: usize, ms: u64) -> usize {
+        let reel: &Reel = &self.reels[reel];
+        let frame: u64 = ms * reel.rate as u64 / 1000;
+        (frame % reel.frames as u64) as usize
+    }
+
+    // Only ever move the head between frames, or the channels swap over
+    fn on_frame(&mut self) {
+        self.speed = f32::from_bits(self.transport.speed.load(Ordering::Relaxed));
+        // No point stretching the tape to the length it already is
+        let stretch: bool = self.transport.stretch.load(Ordering::Relaxed) && self.speed != 1.0;
+        self.grain_len = stretch.then(|| (GRAIN_MS * self.rate as u64 / 1000).max(2) as usize & !1);
+        self.cloud = Cloud::unpack(self.transport.cloud.load(Ordering::Relaxed));
+        if self.cloud.is_none() {
+            self.grains.clear();
+        }
+
+        if let Some((reel, ms, backwards)) = self.transport.take_seek() {
+            let reel: usize = reel.filter(|&r| r < self.reels.len()).unwrap_or(self.voice.head.reel);
+            // Backwards we play whatever led up to `ms`, so start on the frame before it
+            let frame: usize = self.ms_to_frame(reel, ms);
+            let frame: usize = if backwards { next_frame(frame, self.reels[reel].frames, true) } else { frame };
+            self.mark = Voice::at(Head::at(reel, frame, backwards));
+            let repeat_ms: u64 = self.transport.repeat_ms.load(Ordering::Relaxed) as u64;
+            self.repeat_every = (repeat_ms * self.rate as u64 / 1000) as usize;
+            self.until_repeat = self.repeat_every;
+            self.cut_to_mark();
+        } else if self.repeat_every > 0 {
+            self.until_repeat -= 1;
+            if self.until_repeat == 0 {
+                self.until_repeat = self.repeat_every;
+                self.cut_to_mark();
+            }
+        }
+        if let Some(cloud) = self.cloud {
+            self.scatter(cloud);
+        }
+        let frame: u64 = self.voice.head.frame as u64;
+        let rate: u64 = self.reels[self.voice.head.reel].rate as u64;
+        self.transport.position_ms.store(frame * 1000 / rate, Ordering::Relaxed);
+    }
+
+    // Fade over to wherever the last seek landed
+    fn cut_to_mark(&mut self) {
+        // Grains fade themselves, the old ones just play out
+        let fade_ms: u64 = match self.cloud {
+            Some(_) => 0,
+            None => self.transport.crossfade_ms.load(Ordering::Relaxed) as u64,
+        };
+        let len: usize = (fade_ms * self.rate as u64 / 1000) as usize * self.channels as usize;
+        self.fade = (len > 0).then_some(Fade { voice: self.voice, left: len, len });
+        self.voice = self.mark;
+        // Start a cloud right where we land
+        self.until_grain = 0.0;
+    }
+
+    // Start a grain somewhere about the head whenever one's due
+    fn scatter(&mut self, cloud: Cloud) {
+        self.until_grain -= 1.0;
+        if self.until_grain > 0.0 {
+            return;
+        }
+        self.until_grain += self.rate as f32 / cloud.density as f32;
+        if self.grains.len() == MAX_GRAINS {
+            return;
+        }
+        let frames: usize = self.reels[self.voice.head.reel].frames;
+        let spray: i64 = (cloud.spray_ms as u64 * self.rate as u64 / 1000) as i64;
+        let offset: i64 = self.rng.random_range(-spray..=spray);
+        let mut head: Head = self.voice.head;
+        head.frame = (head.frame as i64 + offset).rem_euclid(frames as i64) as usize;
+        let len: usize = (cloud.grain_ms as u64 * self.rate as u64 / 1000).max(1) as usize;
+        self.grains.push(Grain { head, age: 0, len });
+    }
+
+    // Every grain sounding, kept about as loud as the tape however thick the cloud
+    fn read_cloud(&self, cloud: Cloud, channel: usize) -> f32 {
+        let overlap: f32 = cloud.density as f32 * cloud.grain_ms as f32 / 1000.0;
+        let sum: f32 = self.grains.iter()
+            .map(|g| cloud.envelope.gain(g.age as f32 / g.len as f32) * g.head.read(&self.reels, channel))
+            .sum();
+        sum / overlap.max(1.0).sqrt()
+    }
+}
+
+impl Iterator for Deck {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.reels.is_empty() {
+            return None;
+        }
+        if self.channel == 0 {
+            self.on_frame();
+        }
+        let channels: usize = self.channels as usize;
+        let mut sample: f32 = match self.cloud {
+            Some(cloud) => self.read_cloud(cloud, self.channel),
+            None => self.voice.read(&self.reels, self.channel, self.grain_len),
+        };
+
+        // Mix the way out with the way in, linearly
+        if let Some(fade) = self.fade.as_mut() {
+            let gain: f32 = fade.left as f32 / fade.len as f32;
+            let out: f32 = fade.voice.read(&self.reels, self.channel, self.grain_len);
+            sample = sample * (1.0 - gain) + out * gain;
+            fade.left -= 1;
+        }
+
+        // Channels always run forwards, only the frames go whichever way
+        self.channel += 1;
+        if self.channel == channels {
+            self.channel = 0;
+            self.voice.advance(self.speed, &self.reels, self.grain_len);
+            if let Some(fade) = self.fade.as_mut() {
+                fade.voice.advance(self.speed, &self.reels, self.grain_len);
+            }
+            // Grains keep the tape's pitch on a stretch, like everything else
+            let grain_speed: f32 = if self.grain_len.is_some() { 1.0 } else { self.speed };
+            for grain in self.grains.iter_mut() {
+                grain.head.advance(grain_speed, &self.reels);
+                grain.age += 1;
+            }
+            self.grains.retain(|g| g.age < g.len);
+        }
+        if self.fade.is_some_and(|fade| fade.left == 0) {
+            self.fade = None;
+        }
+        Some(sample)
+    }
+}
+
+impl Source for Deck {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    // A loop, so forever
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.transport.seek(pos.as_millis() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp_tape(frames: usize) -> Tape {
+        let samples: Arc<[f32]> = (0..frames).map(|k| k as f32).collect();
+        Tape { path: "ramp".into(), duration_ms: frames as u64, peaks: Vec::new(), samples, channels: 1, rate: 1000 }
+    }
+
+    #[test]
+    fn loops_and_jumps() {
+        let transport = Transport::new();
+        let mut deck = Deck::new(&ramp_tape(10), Arc::clone(&transport));
+        let played: Vec<f32> = deck.by_ref().take(12).collect();
+        assert_eq!(played[9..], [9.0, 0.0, 1.0]);
+
+        transport.seek(5);
+        assert_eq!(deck.next(), Some(5.0));
+        assert_eq!(transport.position_ms(), 5);
+    }
+
+    #[test]
+    fn crossfades_into_a_jump() {
+        let transport = Transport::new();
+        transport.set_crossfade(4);
+        let mut deck = Deck::new(&ramp_tape(100), Arc::clone(&transport));
+        transport.seek(50);
+        let played: Vec<f32> = deck.by_ref().take(5).collect();
+        // Starts entirely on the old head and ends entirely on the new
+        assert_eq!(played[0], 0.0);
+        assert!(played[1] > 0.0 && played[1] < 51.0);
+        assert_eq!(played[4], 54.0);
+    }
+
+    #[test]
+    fn plays_backwards_and_forwards_again() {
+        let transport = Transport::new();
+        let mut deck = Deck::new(&ramp_tape(10), Arc::clone(&transport));
+        transport.seek_back(3);
+        let played: Vec<f32> = deck.by_ref().take(5).collect();
+        assert_eq!(played, [2.0, 1.0, 0.0, 9.0, 8.0]);
+        assert_eq!(transport.position_ms(), 8);
+
+        transport.seek(6);
+        assert_eq!(deck.by_ref().take(2).collect::<Vec<f32>>(), [6.0, 7.0]);
+    }
+
+    #[test]
+    fn varispeed_runs_the_tape_faster_or_slower() {
+        let transport = Transport::new();
+        let mut deck = Deck::new(&ramp_tape(100), Arc::clone(&transport));
+        transport.set_speed(2.0, false);
+        assert_eq!(deck.by_ref().take(3).collect::<Vec<f32>>(), [0.0, 2.0, 4.0]);
+
+        // Half speed lands between frames
+        transport.set_speed(0.5, false);
+        transport.seek(10);
+        assert_eq!(deck.by_ref().take(4).collect::<Vec<f32>>(), [10.0, 10.5, 11.0, 11.5]);
+    }
+
+    #[test]
+    fn stretch_follows_the_head() {
+        let transport = Transport::new();
+        let mut deck = Deck::new(&ramp_tape(1000), Arc::clone(&transport));
+        transport.set_speed(2.0, true);
+        let played: Vec<f32> = deck.by_ref().take(400).collect();
+        assert_eq!(transport.position_ms(), 798);
+        // Each grain walks the ramp one frame at a time, never far behind the head
+        let grain: f32 = GRAIN_MS as f32;
+        assert!(played.iter().enumerate().all(|(k, &s)| s <= 2.0 * k as f32 && s >= 2.0 * k as f32 - 2.0 * grain));
+    }
+
+    #[test]
+    fn stretch_keeps_the_pitch() {
+        // A sine that comes round every 20 frames crosses zero 40 times in 400
+        let samples: Arc<[f32]> = (0..1000).map(|k| (k as f32 * std::f32::consts::TAU / 20.0 + 0.1).sin()).collect();
+        let tape = Tape { path: "sine".into(), duration_ms: 1000, peaks: Vec::new(), samples, channels: 1, rate: 1000 };
+        let crossings = |stretch: bool| {
+            let transport = Transport::new();
+            let deck = Deck::new(&tape, Arc::clone(&transport));
+            transport.set_speed(2.0, stretch);
+            let played: Vec<f32> = deck.take(400).collect();
+            played.windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count()
+        };
+        // Varispeed doubles the pitch, a stretch leaves it be
+        assert!((76..=84).contains(&crossings(false)));
+        assert!((36..=44).contains(&crossings(true)));
+    }
+
+    #[test]
+    fn repeats_from_where_it_landed() {
+        let transport = Transport::new();
+        let mut deck = Deck::new(&ramp_tape(100), Arc::clone(&transport));
+        transport.set_repeat(3);
+        assert_eq!(deck.by_ref().take(4).collect::<Vec<f32>>(), [0.0, 1.0, 2.0, 3.0]);
+        transport.seek(10);
+        assert_eq!(deck.by_ref().take(7).collect::<Vec<f32>>(), [10.0, 11.0, 12.0, 10.0, 11.0, 12.0, 10.0]);
+
+        // Only a seek lets go of the repeat
+        transport.set_repeat(0);
+        assert_eq!(deck.next(), Some(11.0));
+        transport.seek(50);
+        assert_eq!(deck.by_ref().take(4).collect::<Vec<f32>>(), [50.0, 51.0, 52.0, 53.0]);
+    }
+
+    #[test]
+    fn clouds_come_from_where_the_head_is() {
+        // Silence but for the chunk we jump to
+        let samples: Arc<[f32]> = (0..2000).map(|k| if (1000..1500).contains(&k) { 1.0 } else { 0.0 }).collect();
+        let tape = Tape { path: "chunk".into(), duration_ms: 2000, peaks: Vec::new(), samples, channels: 1, rate: 1000 };
+        let transport = Transport::new();
+        let mut deck = Deck::new(&tape, Arc::clone(&transport));
+        transport.set_cloud(Some(Cloud { grain_ms: 20, density: 200, spray_ms: 10, envelope: Envelope::Hann }));
+        assert!(deck.by_ref().take(200).all(|s| s == 0.0));
+
+        transport.seek(1100);
+        let played: Vec<f32> = deck.by_ref().take(300).collect();
+        // A grain every 5 frames, 20 long, so after the first there's always one sounding
+        assert!(played[10..].iter().all(|&s| s > 0.0));
+        assert!(deck.grains.len() <= 5);
+
+        transport.set_cloud(None);
+        assert_eq!(deck.next(), Some(1.0));
+        assert!(deck.grains.is_empty());
+    }
+
+    #[test]
+    fn clouds_pack_into_a_word() {
+        let cloud = Cloud { grain_ms: 120, density: 25, spray_ms: 400, envelope: Envelope::Perc };
+        assert_eq!(Cloud::unpack(Cloud::pack(Some(cloud))), Some(cloud));
+        assert_eq!(Cloud::unpack(Cloud::pack(None)), None);
+        for envelope in Envelope::ALL {
+            assert!(envelope.gain(0.0) < 0.05 && envelope.gain(0.999) < 0.05);
+        }
+    }
+
+    #[test]
+    fn seeks_across_tapes() {
+        // A second tape at twice the rate, read at its own pitch
+        let fast = Tape { rate: 2000, ..ramp_tape(100) };
+        let samples: Arc<[f32]> = (0..10).map(|k| -(k as f32)).collect();
+        let stereo = Tape { path: "stereo".into(), duration_ms: 5, peaks: Vec::new(), samples, channels: 2, rate: 1000 };
+        let transport = Transport::new();
+        let mut deck = Deck::with_tapes(&[ramp_tape(10), fast, stereo], Arc::clone(&transport));
+        transport.seek_tape(1, 20, false);
+        assert_eq!(deck.by_ref().take(3).collect::<Vec<f32>>(), [40.0, 42.0, 44.0]);
+        // A plain seek stays on the tape we're on
+        transport.seek(5);
+        assert_eq!(deck.next(), Some(10.0));
+        assert_eq!(transport.position_ms(), 5);
+        // The mono deck takes the left of a stereo tape
+        transport.seek_tape(2, 4, true);
+        assert_eq!(deck.by_ref().take(2).collect::<Vec<f32>>(), [-6.0, -4.0]);
+        transport.seek_tape(0, 2, false);
+        assert_eq!(deck.next(), Some(2.0));
+    }
+
+    #[test]
+    fn keeps_the_channels_in_order_backwards() {
+        let samples: Arc<[f32]> = [0.0, 0.5, 1.0, 1.5, 2.0, 2.5].into();
+        let tape = Tape { path: "stereo".into(), duration_ms: 3, peaks: Vec::new(), samples, channels: 2, rate: 1000 };
+        let transport = Transport::new();
+        let mut deck = Deck::new(&tape, Arc::clone(&transport));
+        transport.seek_back(3);
+        let played: Vec<f32> = deck.by_ref().take(4).collect();
+        assert_eq!(played, [2.0, 2.5, 1.0, 1.5]);
+    }
+}
@@ -0,0 +1,486 @@
+use rand::Rng;
+
+use std::sync::Arc;
+
+use crate::deck::{Deck, Transport};
+use crate::fx::{Effects, FxParam};
+use crate::kit::{Kit, Pad};
+use crate::modulate::{modulate, Modulated, PatchReading};
+use crate::patch::PatchMatrix;
+use crate::route::{Placement, Routing};
+use crate::settings::{Settings, MAX_STEPS};
+use crate::tape::Tape;
+
+// What came of one step of the engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepOutcome {
+    // The step we're playing now
+    pub position: u32,
+    // Where we sent the playhead on the tape, if we moved it
+    pub seek_ms: Option<u64>,
+    // Which of the kit's tapes this step plays
+    pub tape: usize,
+    // The wires out of this step
+    pub mux_word: u16,
+    // Every wire on the board
+    pub rows: [u16; 16],
+    // Whether this step plays backwards
+    pub backwards: bool,
+    // How fast and loud it plays
+    pub speed: f32,
+    pub volume: f32,
+    // How often it goes back to its start, if it ratchets or stutters
+    pub repeat_ms: Option<u64>,
+    // Where we'll jump once this step is over
+    pub jump_to: Option<u64>,
+    // How long this step lasts
+    pub chunk_len: u64,
+}
+
+// The jump engine with none of the hardware.
+// Give it a tape or a kit to play, then a patch once a step and it works the transport.
+pub struct WaveJumper {
+    pub settings: Settings,
+    transport: Arc<Transport>,
+    effects: Arc<Effects>,
+    routing: Arc<Routing>,
+    kit: Option<Kit>,
+    position: u32,
+    jump_to: Option<u64>,
+    backwards: bool,
+    speed: f32,
+    repeat_ms: Option<u64>,
+    // What the patch said about the step we're on, for turning knobs between steps
+    reading: Option<PatchReading>,
+}
+
+impl WaveJumper {
+    pub fn new(settings: Settings) -> Self {
+        let routing: Arc<Routing> = Routing::new(settings.outputs);
+        let mut engine = WaveJumper { settings, transport: Transport::new(), effects: Effects::new(), routing, kit: None, position: 0, jump_to: None, backwards: false, speed: 1.0, repeat_ms: None, reading: None };
+        engine.rewind();
+        engine
+    }
+
+    // The step before the first, so we come in on the top of the tape
+    fn rewind(&mut self) {
+        self.position = self.steps() - 1;
+        self.jump_to = None;
+        self.backwards = false;
+        self.speed = 1.0;
+        self.repeat_ms = None;
+    }
+
+    // Put a tape on from the top, the deck is what to play it through
+    pub fn load(&mut self, tape: Tape) -> Deck {
+        self.load_kit(Kit::single(tape))
+    }
+
+    // Put every tape in a kit on, starting from the top of the first
+    pub fn load_kit(&mut self, kit: Kit) -> Deck {
+        let deck = Deck::with_tapes(&kit.tapes, Arc::clone(&self.transport));
+        self.kit = Some(kit);
+        self.rewind();
+        deck
+    }
+
+    // The first tape, which sets how long a step is
+    pub fn tape(&self) -> Option<&Tape> {
+        self.kit.as_ref().and_then(|k| k.tapes.first())
+    }
+
+    pub fn kit(&self) -> Option<&Kit> {
+        self.kit.as_ref()
+    }
+
+    // Where `step` sends the playhead
+    fn pad(&self, step: u32) -> Pad {
+        self.kit.as_ref().map_or(Pad { tape: 0, slice: step }, |k| k.pad(step))
+    }
+
+    // Where a pad's slice starts on its tape, and how long it is
+    fn slice(&self, pad: Pad) -> (u64, u64) {
+        let tape_ms: u64 = self.kit.as_ref().and_then(|k| k.tapes.get(pad.tape)).map_or(0, |t| t.duration_ms);
+        let len: u64 = self.settings.chunk_len(tape_ms);
+        (pad.slice as u64 * len, len)
+    }
+
+    pub fn transport(&self) -> &Arc<Transport> {
+        &self.transport
+    }
+
+    // What to play the deck through, see Effects::chain
+    pub fn effects(&self) -> &Arc<Effects> {
+        &self.effects
+    }
+
+    // Where each step sounds, see Routing::route
+    pub fn routing(&self) -> &Arc<Routing> {
+        &self.routing
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.settings.steps.clamp(1, MAX_STEPS)
+    }
+
+    pub fn buffer_ms(&self) -> u64 {
+        self.tape().map_or(0, |t| t.duration_ms)
+    }
+
+    pub fn chunk_len(&self) -> u64 {
+        self.settings.chunk_len(self.buffer_ms())
+    }
+
+    // Where the playhead is on the tape we're playing
+    pub fn tape_ms(&self) -> u64 {
+        let tape: usize = self.pad(self.position).tape;
+        let tape_ms: u64 = self.kit.as_ref().and_then(|k| k.tapes.get(tape)).map_or(0, |t| t.duration_ms);
+        self.transport.position_ms() % tape_ms.max(1)
+    }
+
+    // Everything as set, turned by whatever the patch said about the step we're on
+    fn turned(&self) -> Modulated {
+        let s: &Settings = &self.settings;
+        let base = Modulated { fx: s.fx, volume: s.volume, speed: s.speed_at(self.position), crossfade_ms: s.crossfade_ms };
+        self.reading.map_or(base, |reading| modulate(base, &s.mods, &reading))
+    }
+
+    // How loud the step we're on plays, as set and as the patch turns it
+    pub fn volume(&self) -> f32 {
+        self.turned().volume
+    }
+
+    // How long the crossfade into the next jump is, as set and as the patch turns it
+    pub fn crossfade_ms(&self) -> u32 {
+        self.turned().crossfade_ms
+    }
+
+    // Turn an effect right away rather than come the next step
+    pub fn set_fx(&mut self, param: FxParam, value: f32) {
+        self.settings.fx.set(param, value);
+        self.effects.set(self.turned().fx);
+    }
+
+    // Take the jump we picked last step, or walk on to the next, then pick where to go from here
+    pub fn step(&mut self, patch: &PatchMatrix) -> StepOutcome {
+        let steps: u32 = self.steps();
+        let chunk_len: u64 = self.chunk_len();
+        let from: u32 = self.position;
+        let jumped: Option<u32> = self.jump_to.take().map(|j| j as u32 % steps);
+        self.position = jumped.unwrap_or((self.position + 1) % steps);
+        let pad: Pad = self.pad(self.position);
+        let (slice_ms, slice_len) = self.slice(pad);
+        // A slice of a shorter tape in a kit ends early, rather than running on into the next
+        let chunk_len: u64 = chunk_len.min(slice_len);
+        let mut seek_ms: Option<u64> = jumped.map(|_| slice_ms);
+        if jumped.is_none() {
+            // Walking on carries on down the tape, unless the kit has the next step somewhere else
+            let last: Pad = self.pad(from);
+            let follows: bool = last.tape == pad.tape && pad.slice == (last.slice + 1) % steps;
+            // At our own tempo the tape won't come round on its own
+            let wrapped: bool = self.position == 0 && self.settings.tempo.is_some();
+            if !follows || wrapped {
+                seek_ms = Some(slice_ms);
+            }
+        }
+
+        // Whatever the scanner last read back, with an eye on where we are now
+        patch.follow(self.position);
+        let rows: [u16; 16] = patch.rows();
+        // The ratchet hole isn't anywhere to jump to
+        let ratchet_wire: u16 = self.settings.ratchet_hole.filter(|&h| h < MAX_STEPS).map_or(0, |h| 1 << h);
+        let wired: bool = rows[self.position as usize] & ratchet_wire != 0;
+        let jump_rows: [u16; 16] = rows.map(|row| row & !ratchet_wire);
+        let roll: u32 = rand::rng().random_range(0..100);
+
+        // Let the patch turn whatever it's routed to
+        self.reading = Some(PatchReading {
+            position: self.position,
+            mux_word: jump_rows[self.position as usize],
+            steps,
+            jumped_from: jumped.map(|_| from),
+        });
+        let turned: Modulated = self.turned();
+        self.effects.set(turned.fx);
+        self.transport.set_crossfade(turned.crossfade_ms);
+        let s: &Settings = &self.settings;
+        let step: usize = self.position as usize;
+        self.routing.set(Placement { pan: s.step_pans[step], width: s.width, output: s.step_outputs[step] });
+
+        // Anything but straight through at the tape's own speed leaves the head somewhere else come the next step
+        let was_astray: bool = self.backwards || self.speed != 1.0 || self.repeat_ms.is_some();
+        self.backwards = self.settings.plays_back(self.position, jumped.map(|_| from));
+        self.speed = turned.speed;
+        self.repeat_ms = self.settings.repeat_ms(self.position, chunk_len, wired, roll);
+        self.transport.set_speed(self.speed, self.settings.stretch);
+        self.transport.set_cloud(self.settings.granular.then_some(self.settings.cloud));
+        self.transport.set_repeat(self.repeat_ms.unwrap_or(0) as u32);
+        if self.backwards {
+            // Come in at the end of the step and play it down to the start
+            seek_ms = Some(slice_ms + slice_len);
+        } else if seek_ms.is_none() && (was_astray || self.speed != 1.0 || self.repeat_ms.is_some()) {
+            seek_ms = Some(slice_ms);
+        }
+        if let Some(ms) = seek_ms {
+            self.transport.seek_tape(pad.tape, ms, self.backwards);
+        }
+
+        self.jump_to = self.settings.strategy.pick(&self.position, &jump_rows, steps);
+        StepOutcome {
+            position: self.position,
+            seek_ms,
+            tape: pad.tape,
+            mux_word: rows[self.position as usize],
+            rows,
+            backwards: self.backwards,
+            speed: self.speed,
+            volume: turned.volume,
+            repeat_ms: self.repeat_ms,
+            jump_to: self.jump_to,
+            chunk_len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jump::JumpStrategy;
+    use crate::modulate::ModRoute;
+    use crate::settings::Reverse;
+
+    fn engine(steps: u32) -> WaveJumper {
+        let settings = Settings { steps, strategy: JumpStrategy::Nearest, ..Settings::default() };
+        let mut engine = WaveJumper::new(settings);
+        let tape = Tape {
+            path: "test.wav".into(),
+            duration_ms: 1600,
+            peaks: Vec::new(),
+            samples: vec![0.0; 1600].into(),
+            channels: 1,
+            rate: 1000,
+        };
+        engine.load(tape);
+        engine
+    }
+
+    #[test]
+    fn walks_the_steps_without_wires() {
+        let mut engine = engine(4);
+        let patch = PatchMatrix::from_rows([0; 16]);
+        let positions: Vec<u32> = (0..6).map(|_| engine.step(&patch).position).collect();
+        assert_eq!(positions, vec![0, 1, 2, 3, 0, 1]);
+        assert_eq!(engine.chunk_len(), 400);
+    }
+
+    #[test]
+    fn jumps_where_the_wires_go() {
+        let mut engine = engine(16);
+        let mut rows = [0u16; 16];
+        rows[1] = 1 << 9;
+        let patch = PatchMatrix::from_rows(rows);
+        engine.step(&patch);
+        let here = engine.step(&patch);
+        assert_eq!((here.position, here.jump_to), (1, Some(9)));
+        let there = engine.step(&patch);
+        assert_eq!((there.position, there.seek_ms), (9, Some(900)));
+        assert_eq!(engine.step(&patch).position, 10);
+    }
+
+    #[test]
+    fn plays_marked_steps_backwards() {
+        let mut engine = engine(4);
+        engine.settings.reversed_steps = 1 << 1;
+        let patch = PatchMatrix::from_rows([0; 16]);
+        engine.step(&patch);
+        let marked = engine.step(&patch);
+        assert_eq!((marked.position, marked.backwards, marked.seek_ms), (1, true, Some(800)));
+        // Forwards again from the top of the next step
+        let after = engine.step(&patch);
+        assert_eq!((after.backwards, after.seek_ms), (false, Some(800)));
+        assert_eq!(engine.step(&patch).seek_ms, None);
+    }
+
+    #[test]
+    fn jumping_back_plays_backwards() {
+        let mut engine = engine(16);
+        engine.settings.reverse = Reverse::Back;
+        let mut rows = [0u16; 16];
+        rows[9] = 1 << 2;
+        let patch = PatchMatrix::from_rows(rows);
+        let positions: Vec<(u32, bool)> = (0..12).map(|_| engine.step(&patch))
+            .map(|out| (out.position, out.backwards))
+            .skip(9)
+            .collect();
+        assert_eq!(positions, vec![(9, false), (2, true), (3, false)]);
+    }
+
+    #[test]
+    fn steps_off_speed_start_where_they_should() {
+        let mut engine = engine(4);
+        engine.settings.set_step_speed(1, 2.0);
+        let patch = PatchMatrix::from_rows([0; 16]);
+        let outcomes: Vec<(f32, Option<u64>)> = (0..4).map(|_| engine.step(&patch))
+            .map(|out| (out.speed, out.seek_ms))
+            .collect();
+        assert_eq!(outcomes, vec![(1.0, None), (2.0, Some(400)), (1.0, Some(800)), (1.0, None)]);
+    }
+
+    #[test]
+    fn ratchets_from_the_top_of_the_step() {
+        let mut engine = engine(4);
+        engine.settings.ratchets[1] = 4;
+        engine.settings.ratchet_hole = Some(15);
+        let mut rows = [0u16; 16];
+        rows[2] = 1 << 15;
+        let patch = PatchMatrix::from_rows(rows);
+        let outcomes: Vec<(Option<u64>, Option<u64>)> = (0..4).map(|_| engine.step(&patch))
+            .map(|out| (out.repeat_ms, out.seek_ms))
+            .collect();
+        // Step 2 is wired into the ratchet hole, which is no place to jump
+        assert_eq!(outcomes, vec![(None, None), (Some(100), Some(400)), (Some(200), Some(800)), (None, Some(1200))]);
+        assert_eq!(engine.position(), 3);
+    }
+
+    #[test]
+    fn the_patch_turns_the_effects() {
+        let mut engine = engine(16);
+        engine.settings.mods = vec![ModRoute::parse("wires delay_mix 1").unwrap()];
+        let mut rows = [0u16; 16];
+        rows[0] = (1 << 4) | (1 << 8);
+        let patch = PatchMatrix::from_rows(rows);
+        engine.step(&patch);
+        assert_eq!(engine.effects().params().delay_mix, 0.5);
+        engine.step(&patch);
+        assert_eq!(engine.effects().params().delay_mix, 0.0);
+    }
+
+    #[test]
+    fn knobs_turn_between_steps() {
+        let mut engine = engine(16);
+        engine.settings.mods = vec![ModRoute::parse("wires delay_mix 1").unwrap()];
+        let mut rows = [0u16; 16];
+        rows[0] = (1 << 4) | (1 << 8);
+        engine.step(&PatchMatrix::from_rows(rows));
+        // Heard straight away, with the patch still adding its bit on top
+        engine.set_fx(FxParam::DelayMix, 0.25);
+        assert_eq!(engine.effects().params().delay_mix, 0.75);
+        assert_eq!(engine.settings.fx.delay_mix, 0.25);
+    }
+
+    #[test]
+    fn the_patch_turns_speed_and_volume() {
+        let mut engine = engine(16);
+        engine.settings.mods = ["distance speed", "walked volume -1"].iter().map(|r| ModRoute::parse(r).unwrap()).collect();
+        let mut rows = [0u16; 16];
+        rows[1] = 1 << 9;
+        let patch = PatchMatrix::from_rows(rows);
+        let played: Vec<(u32, f32, f32)> = (0..3).map(|_| engine.step(&patch))
+            .map(|out| (out.position, out.speed, out.volume))
+            .collect();
+        // Step 1 wires right across the board, and only step 9 was jumped to
+        assert_eq!(played, vec![(0, 1.0, 0.0), (1, 2.0, 0.0), (9, 1.0, 1.0)]);
+    }
+
+    #[test]
+    fn volume_changes_keep_the_patch_turn() {
+        let mut engine = engine(16);
+        engine.settings.mods = ["stayed volume -0.5", "stayed crossfade 1"].iter().map(|r| ModRoute::parse(r).unwrap()).collect();
+        let mut rows = [0u16; 16];
+        // Step 0 goes back into itself
+        rows[0] = 1;
+        assert_eq!(engine.step(&PatchMatrix::from_rows(rows)).volume, 0.5);
+        engine.settings.volume = 0.8;
+        assert!((engine.volume() - 0.3).abs() < 1e-6);
+        assert_eq!(engine.crossfade_ms(), 100);
+    }
+
+    #[test]
+    fn kits_send_each_step_to_its_own_tape() {
+        let mut engine = engine(4);
+        let bass: Tape = engine.tape().unwrap().clone();
+        let drums = Tape { path: "drums.wav".into(), duration_ms: 800, ..bass.clone() };
+        let (_, pads) = Kit::parse("0 = bass.wav\n1 = bass.wav\n2 = drums.wav 0\n3 = drums.wav 3").unwrap();
+        engine.load_kit(Kit { path: "test.kit".into(), tapes: vec![bass, drums], pads: Some(pads) });
+        let patch = PatchMatrix::from_rows([0; 16]);
+        let played: Vec<(usize, Option<u64>)> = (0..5).map(|_| engine.step(&patch))
+            .map(|out| (out.tape, out.seek_ms))
+            .collect();
+        // Steps stay as long as a slice of the first tape, and bass walks straight on into bass
+        assert_eq!(played, vec![(0, Some(0)), (0, None), (1, Some(0)), (1, Some(600)), (0, Some(0))]);
+        assert_eq!(engine.chunk_len(), 400);
+    }
+
+    #[test]
+    fn places_each_step() {
+        let mut engine = engine(4);
+        engine.settings.step_pans[1] = -0.5;
+        engine.settings.step_outputs[1] = 2;
+        engine.settings.width = 0.0;
+        let patch = PatchMatrix::from_rows([0; 16]);
+        engine.step(&patch);
+        assert_eq!(engine.routing().placement(), Placement { pan: 0.0, width: 0.0, output: 0 });
+        engine.step(&patch);
+        assert_eq!(engine.routing().placement(), Placement { pan: -0.5, width: 0.0, output: 2 });
+    }
+
+    #[test]
+    fn kit_steps_end_with_their_slice() {
+        let mut engine = engine(4);
+        let long: Tape = engine.tape().unwrap().clone();
+        let short = Tape { path: "short.wav".into(), duration_ms: 400, ..long.clone() };
+        let (_, pads) = Kit::parse("0 = long.wav\n1 = short.wav 1\n2 = short.wav 3\n3 = long.wav").unwrap();
+        engine.load_kit(Kit { path: "test.kit".into(), tapes: vec![long, short], pads: Some(pads) });
+        let patch = PatchMatrix::from_rows([0; 16]);
+        let played: Vec<(usize, Option<u64>, u64)> = (0..4).map(|_| engine.step(&patch))
+            .map(|out| (out.tape, out.seek_ms, out.chunk_len))
+            .collect();
+        // The short tape's slices are 100ms, the last ending right at the end of it
+        assert_eq!(played, vec![(0, None, 400), (1, Some(100), 100), (1, Some(300), 100), (0, Some(1200), 400)]);
+    }
+
+    // A few boards as someone might patch them
+    fn canned() -> Vec<(&'static str, [u16; 16])> {
+        let mut loop_back = [0u16; 16];
+        loop_back[5] = 1 << 2;
+        let mut fan_out = [0u16; 16];
+        fan_out[2] = (1 << 4) | (1 << 11);
+        fan_out[11] = 1 << 13;
+        fan_out[14] = 1 << 7;
+        let mut chain = [0u16; 16];
+        chain[0] = (1 << 3) | (1 << 8);
+        chain[3] = 1 << 12;
+        chain[12] = 1 << 6;
+        chain[9] = 1 << 15;
+        vec![("loop_back", loop_back), ("fan_out", fan_out), ("chain", chain)]
+    }
+
+    #[test]
+    fn plays_canned_patches_step_for_step() {
+        use JumpStrategy::*;
+        let golden: [(JumpStrategy, &str, [u32; 16]); 9] = [
+            (Furthest, "loop_back", [0, 1, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3]),
+            (Nearest, "loop_back", [0, 1, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3]),
+            (Chain, "loop_back", [0, 1, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3, 4, 5, 2, 3]),
+            (Furthest, "fan_out", [0, 1, 2, 11, 13, 14, 7, 8, 9, 10, 11, 13, 14, 7, 8, 9]),
+            (Nearest, "fan_out", [0, 1, 2, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 7, 8, 9]),
+            (Chain, "fan_out", [0, 1, 2, 11, 13, 14, 7, 8, 9, 10, 11, 13, 14, 7, 8, 9]),
+            (Furthest, "chain", [0, 8, 9, 15, 0, 8, 9, 15, 0, 8, 9, 15, 0, 8, 9, 15]),
+            (Nearest, "chain", [0, 3, 12, 6, 7, 8, 9, 15, 0, 3, 12, 6, 7, 8, 9, 15]),
+            (Chain, "chain", [0, 3, 12, 6, 7, 8, 9, 15, 0, 3, 12, 6, 7, 8, 9, 15]),
+        ];
+        let boards = canned();
+        for (strategy, name, want) in golden {
+            let rows = boards.iter().find(|(n, _)| *n == name).unwrap().1;
+            let patch = PatchMatrix::from_rows(rows);
+            let mut engine = engine(16);
+            engine.settings.strategy = strategy;
+            let got: Vec<u32> = (0..16).map(|_| engine.step(&patch).position).collect();
+            assert_eq!(got, want, "{} on {}", strategy.name(), name);
+        }
+    }
+}
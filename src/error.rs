@@ -0,0 +1,91 @@
+use crate::tape::TapeError;
+
+use std::fmt;
+
+// Everything that can go wrong while jumping waves
+#[derive(Debug)]
+pub enum WaveJumperError {
+    #[cfg(feature = "hardware")]
+    Gpio(rpi_pal::gpio::Error),
+    #[cfg(feature = "hardware")]
+    I2c(rpi_pal::i2c::Error),
+    Display(String),
+    Audio(String),
+    Decoding(TapeError),
+    Config(String),
+    Patch(String),
+    Control(String),
+}
+
+impl fmt::Display for WaveJumperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "hardware")]
+            WaveJumperError::Gpio(e) => write!(f, "gpio: {}", e),
+            #[cfg(feature = "hardware")]
+            WaveJumperError::I2c(e) => write!(f, "i2c: {}", e),
+            WaveJumperError::Display(e) => write!(f, "display: {}", e),
+            WaveJumperError::Audio(e) => write!(f, "audio: {}", e),
+            WaveJumperError::Decoding(e) => write!(f, "decoding: {}", e),
+            WaveJumperError::Config(e) => write!(f, "config: {}", e),
+            WaveJumperError::Patch(e) => write!(f, "patch: {}", e),
+            WaveJumperError::Control(e) => write!(f, "control: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WaveJumperError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "hardware")]
+            WaveJumperError::Gpio(e) => Some(e),
+            #[cfg(feature = "hardware")]
+            WaveJumperError::I2c(e) => Some(e),
+            WaveJumperError::Decoding(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "hardware")]
+impl From<rpi_pal::gpio::Error> for WaveJumperError {
+    fn from(e: rpi_pal::gpio::Error) -> Self {
+        WaveJumperError::Gpio(e)
+    }
+}
+
+#[cfg(feature = "hardware")]
+impl From<rpi_pal::i2c::Error> for WaveJumperError {
+    fn from(e: rpi_pal::i2c::Error) -> Self {
+        WaveJumperError::I2c(e)
+    }
+}
+
+impl From<TapeError> for WaveJumperError {
+    fn from(e: TapeError) -> Self {
+        WaveJumperError::Decoding(e)
+    }
+}
+
+impl From<rodio::StreamError> for WaveJumperError {
+    fn from(e: rodio::StreamError) -> Self {
+        WaveJumperError::Audio(e.to_string())
+    }
+}
+
+impl From<rodio::source::SeekError> for WaveJumperError {
+    fn from(e: rodio::source::SeekError) -> Self {
+        WaveJumperError::Audio(e.to_string())
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for WaveJumperError {
+    fn from(e: cpal::DefaultStreamConfigError) -> Self {
+        WaveJumperError::Audio(e.to_string())
+    }
+}
+
+// The ssd1306 crate only gives us a Debug-able DisplayError
+pub fn display_err<E: fmt::Debug>(e: E) -> WaveJumperError {
+    WaveJumperError::Display(format!("{:?}", e))
+}
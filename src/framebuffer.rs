@@ -0,0 +1,129 @@
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use std::convert::Infallible;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::WaveJumperError;
+use crate::oled::Surface;
+
+// An in-memory 1 bit canvas the size of our oled.
+// Handy for screenshots, snapshot tests and anywhere without i2c.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<bool>,
+    dump_dir: Option<PathBuf>,
+    frame: u32,
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        FrameBuffer {
+            size: Size::new(width, height),
+            pixels: vec![false; (width * height) as usize],
+            dump_dir: None,
+            frame: 0,
+        }
+    }
+
+    pub fn oled() -> Self {
+        FrameBuffer::new(128, 64)
+    }
+
+    // Write every painted frame out as a numbered pbm
+    #[allow(dead_code)]
+    pub fn dump_frames_to(mut self, dir: &Path) -> Self {
+        self.dump_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> bool {
+        x < self.size.width && y < self.size.height && self.pixels[(y * self.size.width + x) as usize]
+    }
+
+    // Binary pbm, lit pixels are white so the image looks like the oled
+    pub fn to_pbm(&self) -> Vec<u8> {
+        let mut pbm: Vec<u8> = format!("P4\n{} {}\n", self.size.width, self.size.height).into_bytes();
+        for y in 0..self.size.height {
+            let mut byte: u8 = 0;
+            for x in 0..self.size.width {
+                if !self.pixel(x, y) {
+                    byte |= 0x80 >> (x % 8);
+                }
+                if x % 8 == 7 || x == self.size.width - 1 {
+                    pbm.push(byte);
+                    byte = 0;
+                }
+            }
+        }
+        pbm
+    }
+
+    // Greyscale png, each oled pixel blown up to a `scale` sized square
+    pub fn to_png(&self, scale: u32) -> Result<Vec<u8>, WaveJumperError> {
+        let scale: u32 = scale.max(1);
+        let (w, h): (u32, u32) = (self.size.width * scale, self.size.height * scale);
+        let mut data: Vec<u8> = Vec::with_capacity((w * h) as usize);
+        for y in 0..h {
+            for x in 0..w {
+                data.push(if self.pixel(x / scale, y / scale) { 0xFF } else { 0x00 });
+            }
+        }
+
+        let mut png: Vec<u8> = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, w, h);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let png_err = |e: png::EncodingError| WaveJumperError::Display(format!("png: {}", e));
+        let mut writer = encoder.write_header().map_err(png_err)?;
+        writer.write_image_data(&data).map_err(png_err)?;
+        writer.finish().map_err(png_err)?;
+        Ok(png)
+    }
+
+    // Picks the format from the file extension, anything but .png is a pbm
+    pub fn save(&self, path: &Path) -> Result<(), WaveJumperError> {
+        let is_png: bool = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+        let bytes: Vec<u8> = if is_png { self.to_png(4)? } else { self.to_pbm() };
+        fs::write(path, bytes)
+            .map_err(|e| WaveJumperError::Display(format!("can't save {}: {}", path.display(), e)))
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(pnt, color) in pixels {
+            let in_range: bool = pnt.x >= 0 && pnt.y >= 0
+                && (pnt.x as u32) < self.size.width
+                && (pnt.y as u32) < self.size.height;
+            if in_range {
+                let idx: usize = (pnt.y as u32 * self.size.width + pnt.x as u32) as usize;
+                self.pixels[idx] = color.is_on();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Surface for FrameBuffer {
+    fn flush(&mut self) -> Result<(), WaveJumperError> {
+        let Some(dir) = self.dump_dir.as_ref() else { return Ok(()) };
+        let path: PathBuf = dir.join(format!("frame_{:05}.pbm", self.frame));
+        self.frame += 1;
+        self.save(&path)
+    }
+}
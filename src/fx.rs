@@ -0,0 +1,595 @@
+use rodio::source::SeekError;
+use rodio::Source;
+
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Frames between each effect looking for new parameters
+const FX_POLL_FRAMES: usize = 64;
+// Longest echo we keep tape for
+pub const MAX_DELAY_MS: u32 = 2000;
+// Freeverb's combs and allpasses, tuned at 44.1k
+const REVERB_COMBS: [usize; 4] = [1116, 1188, 1277, 1356];
+const REVERB_ALLPASSES: [usize; 2] = [556, 441];
+// Right is a little longer than left, so the room is wide
+const REVERB_SPREAD: usize = 23;
+const REVERB_DAMPING: f32 = 0.2;
+// How fast the limiter lets go, per second
+const LIMITER_RELEASE_S: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterMode {
+    #[default]
+    Off,
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+impl FilterMode {
+    pub const ALL: [FilterMode; 4] = [FilterMode::Off, FilterMode::LowPass, FilterMode::HighPass, FilterMode::BandPass];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterMode::Off => "off",
+            FilterMode::LowPass => "low",
+            FilterMode::HighPass => "high",
+            FilterMode::BandPass => "band",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        FilterMode::ALL.into_iter().find(|m| m.name() == name)
+    }
+}
+
+// Every effect parameter a controller can reach
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FxParam {
+    Filter,
+    Cutoff,
+    Resonance,
+    Bits,
+    Downsample,
+    Delay,
+    Feedback,
+    DelayMix,
+    Room,
+    ReverbMix,
+}
+
+impl FxParam {
+    pub const ALL: [FxParam; 10] = [
+        FxParam::Filter,
+        FxParam::Cutoff,
+        FxParam::Resonance,
+        FxParam::Bits,
+        FxParam::Downsample,
+        FxParam::Delay,
+        FxParam::Feedback,
+        FxParam::DelayMix,
+        FxParam::Room,
+        FxParam::ReverbMix,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FxParam::Filter => "filter",
+            FxParam::Cutoff => "cutoff",
+            FxParam::Resonance => "resonance",
+            FxParam::Bits => "bits",
+            FxParam::Downsample => "downsample",
+            FxParam::Delay => "delay",
+            FxParam::Feedback => "feedback",
+            FxParam::DelayMix => "delay_mix",
+            FxParam::Room => "room",
+            FxParam::ReverbMix => "reverb_mix",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        FxParam::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+// How the effects are set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FxParams {
+    pub filter: FilterMode,
+    pub cutoff_hz: f32,
+    // 0 is gentle, 1 is about to sing
+    pub resonance: f32,
+    // 16 bits is as good as it gets, so off
+    pub bits: u32,
+    // Hold every sample for this many frames
+    pub downsample: u32,
+    pub delay_ms: u32,
+    pub feedback: f32,
+    pub delay_mix: f32,
+    pub room: f32,
+    pub reverb_mix: f32,
+    // Nothing gets louder than this
+    pub ceiling: f32,
+}
+
+impl Default for FxParams {
+    fn default() -> Self {
+        FxParams {
+            filter: FilterMode::Off,
+            cutoff_hz: 2000.0,
+            resonance: 0.2,
+            bits: 16,
+            downsample: 1,
+            delay_ms: 250,
+            feedback: 0.35,
+            delay_mix: 0.0,
+            room: 0.5,
+            reverb_mix: 0.0,
+            ceiling: 0.95,
+        }
+    }
+}
+
+impl FxParams {
+    // Where a parameter sits from 0 to 1, the way a knob would turn it
+    pub fn get(&self, param: FxParam) -> f32 {
+        match param {
+            FxParam::Filter => FilterMode::ALL.iter().position(|&m| m == self.filter).unwrap_or(0) as f32 / 4.0,
+            FxParam::Cutoff => (self.cutoff_hz / 20.0).log(1000.0),
+            FxParam::Resonance => self.resonance,
+            FxParam::Bits => (16.0 - self.bits as f32) / 14.0,
+            FxParam::Downsample => (self.downsample as f32 - 1.0) / 31.0,
+            FxParam::Delay => (self.delay_ms as f32 - 1.0) / (MAX_DELAY_MS - 1) as f32,
+            FxParam::Feedback => self.feedback / 0.95,
+            FxParam::DelayMix => self.delay_mix,
+            FxParam::Room => self.room,
+            FxParam::ReverbMix => self.reverb_mix,
+        }.clamp(0.0, 1.0)
+    }
+
+    // Turn a parameter's knob to `v`, from 0 to 1.
+    // Anything that isn't a number leaves the knob be, it would only fill the chain with NaNs.
+    pub fn set(&mut self, param: FxParam, v: f32) {
+        if !v.is_finite() {
+            return;
+        }
+        let v: f32 = v.clamp(0.0, 1.0);
+        match param {
+            FxParam::Filter => self.filter = FilterMode::ALL[((v * 4.0) as usize).min(3)],
+            // Ten octaves, evenly
+            FxParam::Cutoff => self.cutoff_hz = 20.0 * 1000f32.powf(v),
+            FxParam::Resonance => self.resonance = v,
+            FxParam::Bits => self.bits = (16.0 - v * 14.0).round() as u32,
+            FxParam::Downsample => self.downsample = (1.0 + v * 31.0).round() as u32,
+            FxParam::Delay => self.delay_ms = (1.0 + v * (MAX_DELAY_MS - 1) as f32).round() as u32,
+            FxParam::Feedback => self.feedback = v * 0.95,
+            FxParam::DelayMix => self.delay_mix = v,
+            FxParam::Room => self.room = v,
+            FxParam::ReverbMix => self.reverb_mix = v,
+        }
+    }
+}
+
+// The instrument's hand on the effects, shared with the audio thread
+#[derive(Debug, Default)]
+pub struct Effects {
+    params: Mutex<FxParams>,
+}
+
+impl Effects {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Effects::default())
+    }
+
+    pub fn set(&self, params: FxParams) {
+        *self.params.lock().unwrap_or_else(|e| e.into_inner()) = params;
+    }
+
+    pub fn params(&self) -> FxParams {
+        *self.params.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Everything after the deck, in the order a pedalboard would have it
+    pub fn chain<S: Source>(self: &Arc<Self>, source: S) -> Limiter<Reverb<Delay<Crusher<Filter<S>>>>> {
+        let filter = Filter::new(source, Arc::clone(self));
+        let crusher = Crusher::new(filter, Arc::clone(self));
+        let delay = Delay::new(crusher, Arc::clone(self));
+        let reverb = Reverb::new(delay, Arc::clone(self));
+        Limiter::new(reverb, Arc::clone(self))
+    }
+}
+
+// An effect's view of the parameters, looked at every so often and never waited on
+struct Knobs {
+    effects: Arc<Effects>,
+    params: FxParams,
+    until_poll: usize,
+}
+
+impl Knobs {
+    fn new(effects: Arc<Effects>) -> Self {
+        let params: FxParams = effects.params();
+        Knobs { effects, params, until_poll: FX_POLL_FRAMES }
+    }
+
+    // Once a frame, on its first channel
+    fn on_frame(&mut self) {
+        self.until_poll -= 1;
+        if self.until_poll == 0 {
+            self.until_poll = FX_POLL_FRAMES;
+            if let Ok(params) = self.effects.params.try_lock() {
+                self.params = *params;
+            }
+        }
+    }
+}
+
+// Every effect is a source wrapped round another, passing the shape of it on
+macro_rules! wraps_source {
+    ($fx:ident) => {
+        impl<S: Source> Source for $fx<S> {
+            fn current_span_len(&self) -> Option<usize> {
+                self.inner.current_span_len()
+            }
+
+            fn channels(&self) -> u16 {
+                self.inner.channels()
+            }
+
+            fn sample_rate(&self) -> u32 {
+                self.inner.sample_rate()
+            }
+
+            fn total_duration(&self) -> Option<Duration> {
+                self.inner.total_duration()
+            }
+
+            fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+                self.inner.try_seek(pos)
+            }
+        }
+    };
+}
+
+// The channel of the frame we're on, and whether it's the first
+fn next_channel(channel: &mut usize, channels: u16) -> (usize, bool) {
+    let here: usize = *channel;
+    *channel = (here + 1) % channels.max(1) as usize;
+    (here, here == 0)
+}
+
+// A state variable filter, the trapezoidal kind so it stays put at any cutoff
+pub struct Filter<S> {
+    inner: S,
+    knobs: Knobs,
+    channel: usize,
+    // Two integrators for each channel
+    state: Vec<[f32; 2]>,
+}
+
+impl<S: Source> Filter<S> {
+    pub fn new(inner: S, effects: Arc<Effects>) -> Self {
+        let state: Vec<[f32; 2]> = vec![[0.0; 2]; inner.channels().max(1) as usize];
+        Filter { inner, knobs: Knobs::new(effects), channel: 0, state }
+    }
+}
+
+impl<S: Source> Iterator for Filter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let v0: f32 = self.inner.next()?;
+        let (channel, first) = next_channel(&mut self.channel, self.inner.channels());
+        if first {
+            self.knobs.on_frame();
+        }
+        let p: FxParams = self.knobs.params;
+        if p.filter == FilterMode::Off {
+            return Some(v0);
+        }
+        let nyquist: f32 = self.inner.sample_rate() as f32 / 2.0;
+        let g: f32 = (PI * p.cutoff_hz.clamp(20.0, nyquist * 0.95) / self.inner.sample_rate() as f32).tan();
+        let k: f32 = 2.0 - 1.95 * p.resonance.clamp(0.0, 1.0);
+        let a1: f32 = 1.0 / (1.0 + g * (g + k));
+        let (a2, a3) = (g * a1, g * g * a1);
+
+        let Some([ic1, ic2]) = self.state.get_mut(channel) else { return Some(v0) };
+        let v3: f32 = v0 - *ic2;
+        let v1: f32 = a1 * *ic1 + a2 * v3;
+        let v2: f32 = *ic2 + a2 * *ic1 + a3 * v3;
+        *ic1 = 2.0 * v1 - *ic1;
+        *ic2 = 2.0 * v2 - *ic2;
+        Some(match p.filter {
+            FilterMode::LowPass => v2,
+            FilterMode::HighPass => v0 - k * v1 - v2,
+            FilterMode::BandPass => v1,
+            FilterMode::Off => v0,
+        })
+    }
+}
+
+wraps_source!(Filter);
+
+// Fewer bits and fewer samples, for that early sampler crunch
+pub struct Crusher<S> {
+    inner: S,
+    knobs: Knobs,
+    channel: usize,
+    held: Vec<f32>,
+    // Frames until we take a new sample
+    until_sample: u32,
+}
+
+impl<S: Source> Crusher<S> {
+    pub fn new(inner: S, effects: Arc<Effects>) -> Self {
+        let held: Vec<f32> = vec![0.0; inner.channels().max(1) as usize];
+        Crusher { inner, knobs: Knobs::new(effects), channel: 0, held, until_sample: 0 }
+    }
+}
+
+impl<S: Source> Iterator for Crusher<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample: f32 = self.inner.next()?;
+        let (channel, first) = next_channel(&mut self.channel, self.inner.channels());
+        if first {
+            self.knobs.on_frame();
+            self.until_sample = self.until_sample.saturating_sub(1);
+        }
+        let p: FxParams = self.knobs.params;
+        if p.bits >= 16 && p.downsample <= 1 {
+            return Some(sample);
+        }
+        if self.until_sample == 0 {
+            let steps: f32 = (1u32 << (p.bits.clamp(1, 16) - 1)) as f32;
+            if let Some(held) = self.held.get_mut(channel) {
+                *held = (sample * steps).round() / steps;
+            }
+            // Hold off till every channel of this frame has its new sample
+            if channel + 1 == self.held.len() {
+                self.until_sample = p.downsample.max(1);
+            }
+        }
+        Some(self.held.get(channel).copied().unwrap_or(sample))
+    }
+}
+
+wraps_source!(Crusher);
+
+// Echoes, fed back into themselves
+pub struct Delay<S> {
+    inner: S,
+    knobs: Knobs,
+    channel: usize,
+    tape: Vec<f32>,
+    at: usize,
+}
+
+impl<S: Source> Delay<S> {
+    pub fn new(inner: S, effects: Arc<Effects>) -> Self {
+        let len: usize = (MAX_DELAY_MS as usize * inner.sample_rate() as usize / 1000).max(1) * inner.channels().max(1) as usize;
+        Delay { inner, knobs: Knobs::new(effects), channel: 0, tape: vec![0.0; len], at: 0 }
+    }
+}
+
+impl<S: Source> Iterator for Delay<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let dry: f32 = self.inner.next()?;
+        let (_, first) = next_channel(&mut self.channel, self.inner.channels());
+        if first {
+            self.knobs.on_frame();
+        }
+        let p: FxParams = self.knobs.params;
+        let channels: usize = self.inner.channels().max(1) as usize;
+        let back: usize = (p.delay_ms.clamp(1, MAX_DELAY_MS) as usize * self.inner.sample_rate() as usize / 1000).max(1) * channels;
+        let len: usize = self.tape.len();
+        let wet: f32 = self.tape[(self.at + len - back.min(len)) % len];
+        // Keep recording even when nobody's listening, so the echoes are there when they are
+        self.tape[self.at] = dry + wet * p.feedback;
+        self.at = (self.at + 1) % len;
+        Some(dry + wet * p.delay_mix)
+    }
+}
+
+wraps_source!(Delay);
+
+// One of Freeverb's combs, with a little damping in its feedback
+#[derive(Debug, Clone)]
+struct Comb {
+    tape: Vec<f32>,
+    at: usize,
+    damped: f32,
+}
+
+impl Comb {
+    fn tick(&mut self, input: f32, feedback: f32) -> f32 {
+        let out: f32 = self.tape[self.at];
+        self.damped = out * (1.0 - REVERB_DAMPING) + self.damped * REVERB_DAMPING;
+        self.tape[self.at] = input + self.damped * feedback;
+        self.at = (self.at + 1) % self.tape.len();
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Allpass {
+    tape: Vec<f32>,
+    at: usize,
+}
+
+impl Allpass {
+    fn tick(&mut self, input: f32) -> f32 {
+        let held: f32 = self.tape[self.at];
+        self.tape[self.at] = input + held * 0.5;
+        self.at = (self.at + 1) % self.tape.len();
+        held - input
+    }
+}
+
+// A small room, Freeverb's way: combs side by side, then allpasses one after another
+#[derive(Debug, Clone)]
+struct Room {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Room {
+    fn new(rate: u32, spread: usize) -> Self {
+        let scale = |len: usize| ((len + spread) * rate as usize / 44_100).max(1);
+        Room {
+            combs: REVERB_COMBS.iter().map(|&len| Comb { tape: vec![0.0; scale(len)], at: 0, damped: 0.0 }).collect(),
+            allpasses: REVERB_ALLPASSES.iter().map(|&len| Allpass { tape: vec![0.0; scale(len)], at: 0 }).collect(),
+        }
+    }
+
+    fn tick(&mut self, input: f32, size: f32) -> f32 {
+        let feedback: f32 = 0.7 + 0.28 * size;
+        let wet: f32 = self.combs.iter_mut().map(|c| c.tick(input, feedback)).sum::<f32>() / self.combs.len() as f32;
+        self.allpasses.iter_mut().fold(wet, |s, a| a.tick(s))
+    }
+}
+
+pub struct Reverb<S> {
+    inner: S,
+    knobs: Knobs,
+    channel: usize,
+    rooms: Vec<Room>,
+}
+
+impl<S: Source> Reverb<S> {
+    pub fn new(inner: S, effects: Arc<Effects>) -> Self {
+        let rate: u32 = inner.sample_rate();
+        let rooms: Vec<Room> = (0..inner.channels().max(1) as usize).map(|k| Room::new(rate, k * REVERB_SPREAD)).collect();
+        Reverb { inner, knobs: Knobs::new(effects), channel: 0, rooms }
+    }
+}
+
+impl<S: Source> Iterator for Reverb<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let dry: f32 = self.inner.next()?;
+        let (channel, first) = next_channel(&mut self.channel, self.inner.channels());
+        if first {
+            self.knobs.on_frame();
+        }
+        let p: FxParams = self.knobs.params;
+        if p.reverb_mix <= 0.0 {
+            return Some(dry);
+        }
+        let wet: f32 = self.rooms.get_mut(channel).map_or(0.0, |room| room.tick(dry, p.room));
+        Some(dry * (1.0 - p.reverb_mix) + wet * p.reverb_mix)
+    }
+}
+
+wraps_source!(Reverb);
+
+// Keeps a loud patch off the rails: quick to duck, slow to let go, every channel together
+pub struct Limiter<S> {
+    inner: S,
+    knobs: Knobs,
+    channel: usize,
+    envelope: f32,
+    release: f32,
+}
+
+impl<S: Source> Limiter<S> {
+    pub fn new(inner: S, effects: Arc<Effects>) -> Self {
+        let samples_per_s: f32 = inner.sample_rate() as f32 * inner.channels().max(1) as f32;
+        let release: f32 = (-1.0 / (LIMITER_RELEASE_S * samples_per_s)).exp();
+        Limiter { inner, knobs: Knobs::new(effects), channel: 0, envelope: 0.0, release }
+    }
+}
+
+impl<S: Source> Iterator for Limiter<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample: f32 = self.inner.next()?;
+        let (_, first) = next_channel(&mut self.channel, self.inner.channels());
+        if first {
+            self.knobs.on_frame();
+        }
+        let ceiling: f32 = self.knobs.params.ceiling.clamp(0.01, 1.0);
+        self.envelope = sample.abs().max(self.envelope * self.release);
+        if self.envelope <= ceiling {
+            return Some(sample);
+        }
+        Some(sample * ceiling / self.envelope)
+    }
+}
+
+wraps_source!(Limiter);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const RATE: u32 = 8000;
+
+    fn sine(hz: f32, len: usize) -> SamplesBuffer {
+        let samples: Vec<f32> = (0..len).map(|k| (k as f32 * 2.0 * PI * hz / RATE as f32).sin()).collect();
+        SamplesBuffer::new(1, RATE, samples)
+    }
+
+    fn loudness(samples: &[f32]) -> f32 {
+        samples.iter().map(|s| s.abs()).fold(0.0, f32::max)
+    }
+
+    fn run(params: FxParams, source: SamplesBuffer) -> Vec<f32> {
+        let effects = Effects::new();
+        effects.set(params);
+        effects.chain(source).collect()
+    }
+
+    #[test]
+    fn knobs_turn_both_ways() {
+        let mut params = FxParams::default();
+        for param in FxParam::ALL {
+            params.set(param, 0.75);
+            assert!((params.get(param) - 0.75).abs() < 0.05, "{}", param.name());
+        }
+        assert_eq!(params.filter, FilterMode::BandPass);
+        let before: FxParams = params;
+        params.set(FxParam::Cutoff, f32::NAN);
+        params.set(FxParam::Room, f32::NEG_INFINITY);
+        assert_eq!(params, before);
+    }
+
+    #[test]
+    fn filters_keep_their_side_of_the_cutoff() {
+        let low = FxParams { filter: FilterMode::LowPass, cutoff_hz: 200.0, ..FxParams::default() };
+        let high = FxParams { filter: FilterMode::HighPass, ..low };
+        // Let the filter settle before we listen
+        let hum: Vec<f32> = run(low, sine(50.0, 4000))[2000..].to_vec();
+        let hiss: Vec<f32> = run(low, sine(3000.0, 4000))[2000..].to_vec();
+        assert!(loudness(&hum) > 0.8 && loudness(&hiss) < 0.05);
+        assert!(loudness(&run(high, sine(50.0, 4000))[2000..]) < 0.1);
+    }
+
+    #[test]
+    fn crusher_holds_and_rounds() {
+        let params = FxParams { bits: 2, downsample: 2, ..FxParams::default() };
+        let played: Vec<f32> = run(params, SamplesBuffer::new(1, RATE, vec![0.1, 0.9, 0.4, -0.3]));
+        assert_eq!(played, vec![0.0, 0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn delay_echoes_and_limiter_holds_the_ceiling() {
+        let params = FxParams { delay_ms: 1, delay_mix: 1.0, feedback: 0.0, ceiling: 1.0, ..FxParams::default() };
+        let mut click: Vec<f32> = vec![0.0; 40];
+        click[0] = 0.5;
+        let played: Vec<f32> = run(params, SamplesBuffer::new(1, RATE, click));
+        // One ms at 8k is 8 samples on
+        assert_eq!((played[0], played[8]), (0.5, 0.5));
+
+        let loud = FxParams { reverb_mix: 0.5, ..FxParams::default() };
+        let played: Vec<f32> = run(loud, SamplesBuffer::new(1, RATE, vec![3.0; 4000]));
+        assert!(loudness(&played) <= 0.95 + 1e-6);
+    }
+}
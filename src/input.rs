@@ -0,0 +1,337 @@
+use rpi_pal::gpio::Bias;
+
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::control::Command;
+use crate::pinio::{DigitalIn, GpioBackend};
+
+// The ways one button can be pressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+    Double,
+}
+
+// The buttons on the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    // The skip button
+    Main,
+    // Pushing the encoder's shaft in
+    Encoder,
+}
+
+// Something the performer did to the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Press(Button, Press),
+    // Clicks of the encoder, clockwise is positive
+    Turn(i32),
+}
+
+// Ignores a contact's chatter, a new level has to hold for the whole window
+#[derive(Debug)]
+pub struct Debounce {
+    window: Duration,
+    level: bool,
+    raw: bool,
+    since: Option<Instant>,
+}
+
+impl Debounce {
+    pub fn new(window: Duration) -> Self {
+        Debounce { window, level: false, raw: false, since: None }
+    }
+
+    pub fn update(&mut self, raw: bool, now: Instant) -> bool {
+        if raw != self.raw || self.since.is_none() {
+            self.raw = raw;
+            self.since = Some(now);
+        }
+        if self.raw != self.level && self.since.is_some_and(|t| now - t >= self.window) {
+            self.level = self.raw;
+        }
+        self.level
+    }
+}
+
+// Turns a button held down over time into presses.
+// A tap is only a short press once we're sure it isn't the start of a double.
+#[derive(Debug)]
+pub struct Gestures {
+    long_press: Duration,
+    double_gap: Duration,
+    down_since: Option<Instant>,
+    up_since: Option<Instant>,
+    // This press has been dealt with, ignore the rest of it
+    spent: bool,
+}
+
+impl Gestures {
+    pub fn new(long_press: Duration, double_gap: Duration) -> Self {
+        Gestures { long_press, double_gap, down_since: None, up_since: None, spent: false }
+    }
+
+    pub fn update(&mut self, down: bool, now: Instant) -> Option<Press> {
+        match (down, self.down_since) {
+            (true, None) => {
+                self.down_since = Some(now);
+                self.spent = false;
+                let tapped: Option<Instant> = self.up_since.take();
+                if tapped.is_some_and(|t| now - t < self.double_gap) {
+                    self.spent = true;
+                    return Some(Press::Double);
+                }
+                // Missed our chance to call the last tap, better late than never
+                tapped.map(|_| Press::Short)
+            }
+            (true, Some(t)) if !self.spent && now - t >= self.long_press => {
+                self.spent = true;
+                Some(Press::Long)
+            }
+            (true, Some(_)) => None,
+            (false, Some(_)) => {
+                self.down_since = None;
+                if !self.spent {
+                    self.up_since = Some(now);
+                }
+                None
+            }
+            (false, None) => {
+                let tapped: bool = self.up_since.is_some_and(|t| now - t >= self.double_gap);
+                if tapped {
+                    self.up_since = None;
+                    return Some(Press::Short);
+                }
+                None
+            }
+        }
+    }
+}
+
+// Which way the quadrature moved, indexed by the last AB and this AB
+const QUADRATURE: [i32; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+// Counts a rotary encoder's gray code into clicks
+#[derive(Debug)]
+pub struct Encoder {
+    steps: i32,
+    last: Option<usize>,
+    edges: i32,
+}
+
+impl Encoder {
+    pub fn new(steps: u32) -> Self {
+        Encoder { steps: steps.max(1) as i32, last: None, edges: 0 }
+    }
+
+    pub fn update(&mut self, a: bool, b: bool) -> Option<i32> {
+        let ab: usize = (a as usize) << 1 | b as usize;
+        // Wherever it was resting when we started is as good a place as any
+        let last: usize = self.last.replace(ab).unwrap_or(ab);
+        self.edges += QUADRATURE[last << 2 | ab];
+        if self.edges.abs() < self.steps {
+            return None;
+        }
+        let dir: i32 = self.edges.signum();
+        self.edges = 0;
+        Some(dir)
+    }
+}
+
+struct PanelButton {
+    which: Button,
+    pin: Box<dyn DigitalIn>,
+    debounce: Debounce,
+    gestures: Gestures,
+}
+
+struct PanelEncoder {
+    a: Box<dyn DigitalIn>,
+    b: Box<dyn DigitalIn>,
+    encoder: Encoder,
+}
+
+// Every control on the panel, polled into a queue of events
+pub struct Inputs {
+    buttons: Vec<PanelButton>,
+    encoder: Option<PanelEncoder>,
+    queue: VecDeque<InputEvent>,
+}
+
+impl Inputs {
+    // Anything that isn't wired up or won't open is left off, we can still perform without it
+    pub fn new(gpio: &dyn GpioBackend, config: &Config) -> Self {
+        let mut buttons: Vec<PanelButton> = Vec::new();
+        // The skip button has its own pull up on the board, the encoder module usually doesn't
+        let wanted = [
+            (Button::Main, config.button_pin, Bias::Off),
+            (Button::Encoder, config.encoder_button_pin, Bias::PullUp),
+        ];
+        for (which, pin, bias) in wanted {
+            let Some(pin) = pin else { continue };
+            match gpio.input(pin, bias) {
+                Ok(pin) => buttons.push(PanelButton {
+                    which,
+                    pin,
+                    debounce: Debounce::new(config.debounce),
+                    gestures: Gestures::new(config.long_press, config.double_gap),
+                }),
+                Err(e) => warn!("@Warning -- no {:?} button: {}", which, e),
+            }
+        }
+
+        let encoder: Option<PanelEncoder> = match (config.encoder_a_pin, config.encoder_b_pin) {
+            (Some(a), Some(b)) => {
+                let pins = gpio.input(a, Bias::PullUp).and_then(|a| Ok((a, gpio.input(b, Bias::PullUp)?)));
+                pins.map_err(|e| warn!("@Warning -- no encoder: {}", e)).ok().map(|(a, b)| PanelEncoder {
+                    a,
+                    b,
+                    encoder: Encoder::new(config.encoder_steps),
+                })
+            }
+            _ => None,
+        };
+        Inputs { buttons, encoder, queue: VecDeque::new() }
+    }
+
+    // Sample every control once, this wants calling every few milliseconds
+    pub fn poll(&mut self, now: Instant) {
+        for button in self.buttons.iter_mut() {
+            // Buttons pull their line down when pressed
+            let down: bool = button.debounce.update(button.pin.is_low(), now);
+            if let Some(press) = button.gestures.update(down, now) {
+                self.queue.push_back(InputEvent::Press(button.which, press));
+            }
+        }
+        if let Some(enc) = self.encoder.as_mut() {
+            // No debouncing here, gray code bouncing back and forth cancels itself out
+            if let Some(dir) = enc.encoder.update(enc.a.is_high(), enc.b.is_high()) {
+                self.queue.push_back(InputEvent::Turn(dir));
+            }
+        }
+    }
+
+    pub fn next_event(&mut self) -> Option<InputEvent> {
+        self.queue.pop_front()
+    }
+
+    // Poll the panel on a thread of its own, so a press is never missed however long a step is
+    pub fn spawn(mut self, every: Duration, sender: Sender<Command>) {
+        thread::spawn(move || loop {
+            self.poll(Instant::now());
+            while let Some(ev) = self.next_event() {
+                // Nobody left to listen
+                if sender.send(Command::Input(ev)).is_err() {
+                    return;
+                }
+            }
+            thread::sleep(every);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pinio::MemGpio;
+    use rpi_pal::gpio::Level;
+
+    const BUTTON: u8 = 26;
+    const ENC_A: u8 = 12;
+    const ENC_B: u8 = 13;
+    const ENC_SW: u8 = 19;
+
+    fn panel() -> (MemGpio, Inputs) {
+        let gpio = MemGpio::new();
+        let config = Config {
+            button_pin: Some(BUTTON),
+            encoder_a_pin: Some(ENC_A),
+            encoder_b_pin: Some(ENC_B),
+            encoder_button_pin: Some(ENC_SW),
+            ..Config::default()
+        };
+        // Everything at rest is pulled up
+        for pin in [BUTTON, ENC_A, ENC_B, ENC_SW] {
+            gpio.set(pin, Level::High);
+        }
+        let inputs = Inputs::new(&gpio, &config);
+        (gpio, inputs)
+    }
+
+    // Play a script of (ms, pin, level) edges into the panel, polling every ms, and collect the events
+    fn play(script: &[(u64, u8, Level)], until_ms: u64) -> Vec<InputEvent> {
+        let (gpio, mut inputs) = panel();
+        let t0: Instant = Instant::now();
+        let mut events: Vec<InputEvent> = Vec::new();
+        for ms in 0..=until_ms {
+            for &(_, pin, lv) in script.iter().filter(|&&(at, _, _)| at == ms) {
+                gpio.set(pin, lv);
+            }
+            inputs.poll(t0 + Duration::from_millis(ms));
+            events.extend(std::iter::from_fn(|| inputs.next_event()));
+        }
+        events
+    }
+
+    fn tap(at: u64, pin: u8, ms: u64) -> [(u64, u8, Level); 2] {
+        [(at, pin, Level::Low), (at + ms, pin, Level::High)]
+    }
+
+    #[test]
+    fn tap_is_short_once_the_gap_passes() {
+        assert_eq!(play(&tap(0, BUTTON, 80), 200), vec![]);
+        assert_eq!(play(&tap(0, BUTTON, 80), 500), vec![InputEvent::Press(Button::Main, Press::Short)]);
+    }
+
+    #[test]
+    fn hold_is_long_only_once() {
+        let events = play(&tap(0, BUTTON, 1500), 2500);
+        assert_eq!(events, vec![InputEvent::Press(Button::Main, Press::Long)]);
+    }
+
+    #[test]
+    fn two_taps_are_a_double() {
+        let script: Vec<_> = [tap(0, ENC_SW, 80), tap(200, ENC_SW, 80)].concat();
+        assert_eq!(play(&script, 1000), vec![InputEvent::Press(Button::Encoder, Press::Double)]);
+    }
+
+    #[test]
+    fn chatter_is_debounced() {
+        // A scruffy contact bouncing for a few ms either side of one tap
+        let script = [
+            (0, BUTTON, Level::Low), (1, BUTTON, Level::High), (2, BUTTON, Level::Low),
+            (3, BUTTON, Level::High), (4, BUTTON, Level::Low),
+            (80, BUTTON, Level::High), (82, BUTTON, Level::Low), (83, BUTTON, Level::High),
+        ];
+        assert_eq!(play(&script, 600), vec![InputEvent::Press(Button::Main, Press::Short)]);
+    }
+
+    // One click of the encoder, as the four edges of gray code
+    fn click(at: u64, clockwise: bool) -> Vec<(u64, u8, Level)> {
+        let (first, second) = if clockwise { (ENC_A, ENC_B) } else { (ENC_B, ENC_A) };
+        vec![
+            (at, first, Level::Low),
+            (at + 2, second, Level::Low),
+            (at + 4, first, Level::High),
+            (at + 6, second, Level::High),
+        ]
+    }
+
+    #[test]
+    fn encoder_counts_clicks_both_ways() {
+        let script: Vec<_> = [click(1, true), click(11, true), click(21, false)].concat();
+        assert_eq!(play(&script, 40), vec![InputEvent::Turn(1), InputEvent::Turn(1), InputEvent::Turn(-1)]);
+    }
+
+    #[test]
+    fn half_a_click_is_nothing() {
+        let script: Vec<_> = click(1, true).into_iter().take(2).collect();
+        assert_eq!(play(&script, 40), vec![]);
+    }
+}
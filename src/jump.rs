@@ -0,0 +1,257 @@
+use rand::Rng;
+
+pub fn bit_at(value: &u16, idx: u32) -> u8 {
+    ((value >> idx) & 1) as u8
+}
+
+pub fn get_bitidx_at_maxdelta(mark: &u32, value: &u16, modulo: u32) -> Option<u64> {
+    let mut delta: u32 = 0;
+    let mut idx_maxdelta: Option<u64> = None;
+    while delta < (modulo / 2) {
+        delta += 1;
+        let lft: u32 = (mark + modulo - delta) % modulo;
+        let rht: u32 = (mark + delta) % modulo;
+        if bit_at(value, lft) != 0 {
+            idx_maxdelta = Some(lft as u64);
+        }
+        if bit_at(value, rht) != 0 {
+            idx_maxdelta = Some(rht as u64);
+        }
+    }
+    idx_maxdelta
+}
+
+// The closest connection either side of `mark`
+pub fn get_bitidx_at_mindelta(mark: &u32, value: &u16, modulo: u32) -> Option<u64> {
+    for delta in 1..=(modulo / 2) {
+        let rht: u32 = (mark + delta) % modulo;
+        let lft: u32 = (mark + modulo - delta) % modulo;
+        if bit_at(value, rht) != 0 {
+            return Some(rht as u64);
+        }
+        if bit_at(value, lft) != 0 {
+            return Some(lft as u64);
+        }
+    }
+    None
+}
+
+// Any connection but `mark` itself, chosen at random
+pub fn get_bitidx_at_random(mark: &u32, value: &u16, modulo: u32) -> Option<u64> {
+    let candidates: Vec<u32> = (0..modulo)
+        .filter(|&i| i != *mark && bit_at(value, i) != 0)
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    let k: usize = rand::rng().random_range(0..candidates.len());
+    Some(candidates[k] as u64)
+}
+
+// How many wires deep we look when planning a chain
+const CHAIN_LOOKAHEAD: u32 = 4;
+
+// The most wires we can follow on from `mark` without landing anywhere twice
+fn chain_len(mark: u32, rows: &[u16], modulo: u32, seen: u16, depth: u32) -> u32 {
+    if depth == 0 {
+        return 0;
+    }
+    let word: u16 = rows.get(mark as usize).copied().unwrap_or(0);
+    (0..modulo)
+        .filter(|&i| bit_at(&word, i) != 0 && bit_at(&seen, i) == 0)
+        .map(|i| 1 + chain_len(i, rows, modulo, seen | 1 << i, depth - 1))
+        .max()
+        .unwrap_or(0)
+}
+
+// The connection that leads on through the most wires after it, the nearest if there's a tie
+pub fn get_bitidx_at_longest_chain(mark: &u32, rows: &[u16], modulo: u32) -> Option<u64> {
+    let word: u16 = rows.get(*mark as usize).copied().unwrap_or(0);
+    let seen: u16 = 1 << mark;
+    (1..modulo)
+        .map(|delta| (mark + delta) % modulo)
+        .filter(|&i| bit_at(&word, i) != 0)
+        .map(|i| (i, chain_len(i, rows, modulo, seen | 1 << i, CHAIN_LOOKAHEAD - 1)))
+        .fold(None, |best: Option<(u32, u32)>, (i, len)| match best {
+            Some((_, most)) if most >= len => best,
+            _ => Some((i, len)),
+        })
+        .map(|(i, _)| i as u64)
+}
+
+// How we pick where to jump when a step has several wires
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum JumpStrategy {
+    #[default] Furthest,
+    Nearest,
+    Random,
+    // Plans a few jumps ahead through the whole patch
+    Chain,
+}
+
+impl JumpStrategy {
+    pub const ALL: [JumpStrategy; 4] = [
+        JumpStrategy::Furthest,
+        JumpStrategy::Nearest,
+        JumpStrategy::Random,
+        JumpStrategy::Chain,
+    ];
+
+    pub fn next(self) -> Self {
+        match self {
+            JumpStrategy::Furthest => JumpStrategy::Nearest,
+            JumpStrategy::Nearest => JumpStrategy::Random,
+            JumpStrategy::Random => JumpStrategy::Chain,
+            JumpStrategy::Chain => JumpStrategy::Furthest,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JumpStrategy::Furthest => "furthest",
+            JumpStrategy::Nearest => "nearest",
+            JumpStrategy::Random => "random",
+            JumpStrategy::Chain => "chain",
+        }
+    }
+
+    // `rows` is the whole patch, the wires out of each step
+    pub fn pick(self, mark: &u32, rows: &[u16], modulo: u32) -> Option<u64> {
+        let value: &u16 = rows.get(*mark as usize).unwrap_or(&0);
+        match self {
+            JumpStrategy::Furthest => get_bitidx_at_maxdelta(mark, value, modulo),
+            JumpStrategy::Nearest => get_bitidx_at_mindelta(mark, value, modulo),
+            JumpStrategy::Random => get_bitidx_at_random(mark, value, modulo),
+            JumpStrategy::Chain => get_bitidx_at_longest_chain(mark, rows, modulo),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // How far apart two steps are, going whichever way round is shorter
+    fn distance(a: u32, b: u32, modulo: u32) -> u32 {
+        let d: u32 = (a + modulo - b) % modulo;
+        d.min(modulo - d)
+    }
+
+    // Every connection a strategy could take from `mark`
+    fn candidates(mark: u32, value: u16, modulo: u32) -> Vec<u32> {
+        (0..modulo).filter(|&i| i != mark % modulo && bit_at(&value, i) != 0).collect()
+    }
+
+    #[test]
+    fn bit_at_reads_one_bit() {
+        assert_eq!(bit_at(&0b1010, 0), 0);
+        assert_eq!(bit_at(&0b1010, 1), 1);
+        assert_eq!(bit_at(&0x8000, 15), 1);
+    }
+
+    #[test]
+    fn maxdelta_edge_cases() {
+        // Nothing but a wire back to ourselves
+        assert_eq!(get_bitidx_at_maxdelta(&3, &(1 << 3), 16), None);
+        // Straight across is as far as it gets, from either side
+        assert_eq!(get_bitidx_at_maxdelta(&0, &(1 << 8 | 1 << 1), 16), Some(8));
+        // A tie goes right
+        assert_eq!(get_bitidx_at_maxdelta(&4, &(1 << 1 | 1 << 7), 16), Some(7));
+        // Wires past the last step are ignored
+        assert_eq!(get_bitidx_at_maxdelta(&0, &(1 << 12), 8), None);
+        // A mark past the end wraps round
+        assert_eq!(get_bitidx_at_maxdelta(&17, &(1 << 5), 16), get_bitidx_at_maxdelta(&1, &(1 << 5), 16));
+        // Too few steps to go anywhere
+        assert_eq!(get_bitidx_at_maxdelta(&0, &u16::MAX, 1), None);
+        assert_eq!(get_bitidx_at_maxdelta(&0, &u16::MAX, 0), None);
+    }
+
+    #[test]
+    fn maxdelta_wraps_left_on_any_number_of_steps() {
+        // Left of step 0 of 12 is 11, wherever 2^32 would have landed
+        assert_eq!(get_bitidx_at_maxdelta(&0, &(1 << 11), 12), Some(11));
+        // One step left is nearer than two right
+        assert_eq!(get_bitidx_at_maxdelta(&0, &(1 << 11 | 1 << 2), 12), Some(2));
+        assert_eq!(get_bitidx_at_maxdelta(&2, &(1 << 9), 12), Some(9));
+    }
+
+    #[test]
+    fn chain_follows_the_wires_furthest() {
+        let mut rows = [0u16; 16];
+        // 0 can go to 1, a dead end, or 8, which carries on to 12 and back round to 2
+        rows[0] = 1 << 1 | 1 << 8;
+        rows[8] = 1 << 12;
+        rows[12] = 1 << 2;
+        assert_eq!(JumpStrategy::Chain.pick(&0, &rows, 16), Some(8));
+        assert_eq!(JumpStrategy::Nearest.pick(&0, &rows, 16), Some(1));
+        // Only the dead end left
+        rows[0] = 1 << 1;
+        assert_eq!(JumpStrategy::Chain.pick(&0, &rows, 16), Some(1));
+        assert_eq!(JumpStrategy::Chain.pick(&4, &rows, 16), None);
+    }
+
+    proptest! {
+        #[test]
+        fn bit_at_agrees_with_a_mask(value: u16, idx in 0u32..16) {
+            prop_assert_eq!(bit_at(&value, idx) == 1, value & (1 << idx) != 0);
+        }
+
+        #[test]
+        fn maxdelta_goes_furthest(mark in 0u32..16, value: u16, modulo in 1u32..=16) {
+            let mark: u32 = mark % modulo;
+            let found: Option<u64> = get_bitidx_at_maxdelta(&mark, &value, modulo);
+            let options: Vec<u32> = candidates(mark, value, modulo);
+            prop_assert_eq!(found.is_none(), options.is_empty());
+            if let Some(k) = found.map(|k| k as u32) {
+                prop_assert!(options.contains(&k));
+                let furthest: u32 = options.iter().map(|&i| distance(i, mark, modulo)).max().unwrap();
+                prop_assert_eq!(distance(k, mark, modulo), furthest);
+                // Ties go to the right of the mark
+                if options.contains(&((mark + furthest) % modulo)) {
+                    prop_assert_eq!(k, (mark + furthest) % modulo);
+                }
+            }
+        }
+
+        #[test]
+        fn mindelta_goes_nearest(mark in 0u32..16, value: u16, modulo in 1u32..=16) {
+            let mark: u32 = mark % modulo;
+            let found: Option<u64> = get_bitidx_at_mindelta(&mark, &value, modulo);
+            let options: Vec<u32> = candidates(mark, value, modulo);
+            prop_assert_eq!(found.is_none(), options.is_empty());
+            if let Some(k) = found.map(|k| k as u32) {
+                let nearest: u32 = options.iter().map(|&i| distance(i, mark, modulo)).min().unwrap();
+                prop_assert_eq!(distance(k, mark, modulo), nearest);
+                if options.contains(&((mark + nearest) % modulo)) {
+                    prop_assert_eq!(k, (mark + nearest) % modulo);
+                }
+            }
+        }
+
+        #[test]
+        fn marks_wrap_round(mark in 0u32..64, value: u16, modulo in 1u32..=16) {
+            prop_assert_eq!(
+                get_bitidx_at_maxdelta(&mark, &value, modulo),
+                get_bitidx_at_maxdelta(&(mark % modulo), &value, modulo)
+            );
+            prop_assert_eq!(
+                get_bitidx_at_mindelta(&mark, &value, modulo),
+                get_bitidx_at_mindelta(&(mark % modulo), &value, modulo)
+            );
+        }
+
+        #[test]
+        fn every_strategy_takes_a_real_wire(rows: [u16; 16], mark in 0u32..16, modulo in 1u32..=16) {
+            let mark: u32 = mark % modulo;
+            let options: Vec<u32> = candidates(mark, rows[mark as usize], modulo);
+            for strategy in JumpStrategy::ALL {
+                let found: Option<u64> = strategy.pick(&mark, &rows, modulo);
+                prop_assert_eq!(found.is_none(), options.is_empty(), "{:?}", strategy);
+                if let Some(k) = found {
+                    prop_assert!(options.contains(&(k as u32)), "{:?} took {}", strategy, k);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+
+use crate::settings::MAX_STEPS;
+use crate::tape::{Tape, TapeError};
+
+// What a kit file is called, next to the wavs in a bank
+pub const KIT_EXT: &str = "kit";
+
+// The letter a kit's tape goes by, A being its first. Past Z they all look alike.
+pub fn tape_letter(tape: usize) -> char {
+    (b'A'..=b'Z').nth(tape).map_or('?', char::from)
+}
+
+// Where one channel sends the tape: a slice of one of the kit's tapes,
+// cut into as many slices as there are steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pad {
+    pub tape: usize,
+    pub slice: u32,
+}
+
+// Tapes laid out over the channels like a drum rack, or a lone tape played straight
+#[derive(Clone)]
+pub struct Kit {
+    pub path: String,
+    pub tapes: Vec<Tape>,
+    // Which tape and slice each channel plays, None for a lone tape
+    pub pads: Option<[Pad; MAX_STEPS as usize]>,
+}
+
+impl Kit {
+    pub fn single(tape: Tape) -> Self {
+        Kit { path: tape.path.clone(), tapes: vec![tape], pads: None }
+    }
+
+    pub fn is_kit(path: &str) -> bool {
+        Path::new(path).extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case(KIT_EXT))
+    }
+
+    // A kit file, or any wav on its own
+    pub fn open(path: &str) -> Result<Self, TapeError> {
+        if !Kit::is_kit(path) {
+            return Tape::open(path).map(Kit::single)?.playable();
+        }
+        let text: String = fs::read_to_string(path).map_err(|e| TapeError::Missing(path.into(), e))?;
+        let (wavs, pads) = Kit::parse(&text).map_err(|why| TapeError::BadKit(path.into(), why))?;
+        // Wavs are found from wherever the kit is
+        let dir: &Path = Path::new(path).parent().unwrap_or(Path::new("."));
+        let tapes: Vec<Tape> = wavs.iter()
+            .map(|wav| Tape::open(&dir.join(wav).to_string_lossy()))
+            .collect::<Result<_, _>>()?;
+        Kit { path: path.into(), tapes, pads: Some(pads) }.playable()
+    }
+
+    // A deck won't play anything if one of its tapes is silent, so that tape is as bad as a missing one
+    fn playable(self) -> Result<Self, TapeError> {
+        match self.tapes.iter().find(|t| t.frames() == 0) {
+            Some(silent) => Err(TapeError::Silent(silent.path.clone())),
+            None => Ok(self),
+        }
+    }
+
+    // `channel = wav [slice]` a line, the slice being the channel's own unless said.
+    // Channels left out play the first tape, as if it were on its own.
+    pub fn parse(text: &str) -> Result<(Vec<String>, [Pad; MAX_STEPS as usize]), String> {
+        let mut wavs: Vec<String> = Vec::new();
+        let mut pads: [Pad; MAX_STEPS as usize] = std::array::from_fn(|k| Pad { tape: 0, slice: k as u32 });
+        for (k, line) in text.lines().enumerate() {
+            let line: &str = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad_line = |why: String| format!("line {}: {}", k + 1, why);
+            let (channel, value) = line.split_once('=')
+                .ok_or_else(|| bad_line(format!("expected `channel = wav [slice]`, got `{}`", line)))?;
+            let channel: usize = channel.trim().parse::<usize>().ok().filter(|&c| c < MAX_STEPS as usize)
+                .ok_or_else(|| bad_line(format!("{} isn't a channel", channel.trim())))?;
+            let (wav, slice) = match value.split_whitespace().collect::<Vec<&str>>()[..] {
+                [wav] => (wav, channel as u32),
+                [wav, slice] => (wav, slice.parse::<u32>().ok().filter(|&s| s < MAX_STEPS)
+                    .ok_or_else(|| bad_line(format!("{} isn't a slice", slice)))?),
+                _ => return Err(bad_line(format!("expected `wav [slice]`, got `{}`", value.trim()))),
+            };
+            let tape: usize = wavs.iter().position(|w| w == wav).unwrap_or_else(|| {
+                wavs.push(wav.into());
+                wavs.len() - 1
+            });
+            pads[channel] = Pad { tape, slice };
+        }
+        if wavs.is_empty() {
+            return Err("no wavs in the kit".into());
+        }
+        Ok((wavs, pads))
+    }
+
+    // Where `step` sends the tape
+    pub fn pad(&self, step: u32) -> Pad {
+        match self.pads {
+            Some(pads) => pads[step as usize % pads.len()],
+            None => Pad { tape: 0, slice: step },
+        }
+    }
+
+    // A letter for the tape on each channel, for the oled
+    pub fn letters(&self) -> Option<[char; MAX_STEPS as usize]> {
+        self.pads.map(|pads| pads.map(|pad| tape_letter(pad.tape)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_kit() {
+        let (wavs, pads) = Kit::parse("# bass up top\n0 = bass.wav\n1 = bass.wav 5\n8 = drums.wav 0  # kick").unwrap();
+        assert_eq!(wavs, vec!["bass.wav", "drums.wav"]);
+        assert_eq!(pads[1], Pad { tape: 0, slice: 5 });
+        assert_eq!(pads[8], Pad { tape: 1, slice: 0 });
+        // Left out, so the first tape where it would be anyway
+        assert_eq!(pads[3], Pad { tape: 0, slice: 3 });
+
+        assert!(Kit::parse("16 = bass.wav").is_err());
+        assert!(Kit::parse("0 = bass.wav 16").is_err());
+        assert!(Kit::parse("0 bass.wav").is_err());
+        assert!(Kit::parse("# nothing").is_err());
+        assert!(Kit::is_kit("./assets/house.KIT") && !Kit::is_kit("./assets/house.wav"));
+    }
+
+    #[test]
+    fn silent_tapes_sink_the_kit() {
+        let tape = |path: &str, samples: usize| Tape {
+            path: path.into(), duration_ms: 100, peaks: Vec::new(), samples: vec![0.0; samples].into(), channels: 2, rate: 1000,
+        };
+        let (_, pads) = Kit::parse("0 = bass.wav\n1 = blank.wav").unwrap();
+        let kit = Kit { path: "test.kit".into(), tapes: vec![tape("bass.wav", 200), tape("blank.wav", 1)], pads: Some(pads) };
+        // Half a frame is no frames at all
+        assert!(matches!(kit.playable(), Err(TapeError::Silent(path)) if path == "blank.wav"));
+    }
+
+    #[test]
+    fn letters_run_out_at_z() {
+        assert_eq!((tape_letter(0), tape_letter(25), tape_letter(26)), ('A', 'Z', '?'));
+    }
+}
@@ -0,0 +1,55 @@
+#![allow(non_upper_case_globals)]
+
+// The jump engine and everything it needs to play tapes off a patch.
+// Nothing here needs a pi, build with `default-features = false` to embed it anywhere.
+pub mod engine;
+pub mod jump;
+pub mod deck;
+pub mod settings;
+pub mod kit;
+pub mod fx;
+pub mod modulate;
+pub mod route;
+pub mod tape;
+pub mod patch;
+pub mod config;
+pub mod error;
+
+// The pins, the oled, the terminal and our controllers, for the binary
+#[cfg(feature = "hardware")]
+#[macro_use]
+pub mod console;
+#[cfg(feature = "hardware")]
+pub mod oled;
+#[cfg(feature = "hardware")]
+pub mod mux;
+#[cfg(feature = "hardware")]
+pub mod pinio;
+#[cfg(feature = "hardware")]
+pub mod uifb;
+#[cfg(feature = "hardware")]
+pub mod prelude;
+#[cfg(feature = "hardware")]
+pub mod framebuffer;
+#[cfg(feature = "hardware")]
+pub mod sim;
+#[cfg(feature = "hardware")]
+pub mod control;
+#[cfg(feature = "hardware")]
+pub mod tui;
+#[cfg(feature = "hardware")]
+pub mod input;
+#[cfg(feature = "hardware")]
+pub mod menu;
+#[cfg(feature = "hardware")]
+pub mod selftest;
+#[cfg(feature = "hardware")]
+pub mod osc;
+#[cfg(feature = "hardware")]
+pub mod midi;
+#[cfg(feature = "hardware")]
+pub mod ui;
+#[cfg(feature = "hardware")]
+pub mod shelf;
+
+pub use engine::{StepOutcome, WaveJumper};
@@ -1,221 +1,178 @@
 #![allow(non_upper_case_globals)]
 
-mod oled;
-mod mux;
-mod pinio;
-mod uifb;
-mod prelude;
-
-use mux::*;
-use oled::*;
-use pinio::*;
-use uifb::*;
-use prelude::*;
-
-fn bit_at(value: &u16, idx: u32) -> u8 {
-    ((value >> idx) & 1) as u8
+use wave_jumper::{say, warn};
+use wave_jumper::mux::*;
+use wave_jumper::oled::*;
+use wave_jumper::patch::*;
+use wave_jumper::pinio::*;
+use wave_jumper::uifb::*;
+use wave_jumper::prelude::*;
+use wave_jumper::tape::*;
+use wave_jumper::error::*;
+use wave_jumper::framebuffer::*;
+use wave_jumper::sim::*;
+use wave_jumper::control::*;
+use wave_jumper::settings::*;
+use wave_jumper::input::*;
+use wave_jumper::menu::*;
+use wave_jumper::config::*;
+use wave_jumper::{midi, osc, selftest, tui};
+use wave_jumper::engine::*;
+use wave_jumper::shelf::*;
+use wave_jumper::ui::*;
+
+use std::sync::Arc;
+
+// How long we wait on orders while paused before looking around again
+const PAUSED_WAIT: Duration = Duration::from_millis(100);
+
+// Render the UI with a canned patch to a png or pbm, no hardware required
+fn save_screenshot(path: &str) -> Result<(), WaveJumperError> {
+    let title: String = get_wav_from_local_assets().ok()
+        .and_then(|w| w.into_iter().next())
+        .unwrap_or_else(|| "./assets/".into());
+    let mut screen = Display::new(FrameBuffer::oled());
+    draw_demo_ui(&mut screen, &title)?;
+    screen.surface().save(std::path::Path::new(path))?;
+    say!("@Saved screenshot to {}", path);
+    Ok(())
 }
 
-fn get_bitidx_at_maxdelta(mark: &u32, value: &u16, modulo: u32) -> Option<u64> {
-    let mut delta: u32 = 0;
-    let mut idx_maxdelta: Option<u64> = None;
-    while delta < (modulo / 2) {
-        delta += 1;
-        let lft: u32 = mark.wrapping_sub(delta) % modulo;
-        let rht: u32 = mark.wrapping_add(delta) % modulo;
-        if bit_at(value, lft) != 0 {
-            idx_maxdelta = Some(lft as u64);
-        }
-        if bit_at(value, rht) != 0 {
-            idx_maxdelta = Some(rht as u64);
-        }
+// Test or calibrate the patch bay instead of playing it
+fn check_board<S: Surface>(how: &str, gpio: &dyn GpioBackend, oled: &mut Display<S>, config: &Config, save: bool) -> Result<(), WaveJumperError> {
+    if how != "--calibrate" {
+        return selftest::run(gpio, oled, config).map(|_| ());
     }
-    return idx_maxdelta;
-}
-
-fn get_wav_from_local_assets() -> Result<Vec<String>, std::io::Error> {
-    let entries: ReadDir = read_dir("./assets/")?;
-    let ok_entries = entries.filter_map(|res| res.ok());
-    let paths = ok_entries.map(|e| e.path());
-    
-    let waves = paths.filter(|p| {
-        let ext: Option<&OsStr> = p.extension();
-        let ext_str: Option<&str> = ext.and_then(|e| e.to_str());
-        ext_str.map_or(false, |e| e.eq_ignore_ascii_case("wav"))
-    });
-
-    let wav_string = waves.map(|p| p.to_string_lossy().into_owned());
-    Ok(wav_string.collect())
-}
-
-fn get_decoded_wav(mf: &str) -> DecoderBuilder<File> {
-    let file = File::open(mf).unwrap();
-    let len = file.metadata().unwrap().len();
-    DecoderBuilder::new()
-        .with_data(file)
-        .with_hint("wav")
-        .with_byte_len(len)
-        .with_seekable(true)
+    let map: ChannelMap = selftest::calibrate(gpio, oled, config)?;
+    say!("@Demux holes are on lines {:?}", map.demux);
+    say!("@Mux holes are on lines {:?}", map.mux);
+    if save {
+        Config::save_channel_map(CONFIG_PATH, &map)?;
+        say!("@Saved the channel map to {}", CONFIG_PATH);
+    }
+    Ok(())
 }
 
-fn get_wav_duration(dat: DecoderBuilder<File>) -> u64 {
-    let source = dat.build().unwrap();
-    let buffer_duration: Duration = source.total_duration().unwrap();
-    let buffer_ms: u64 = buffer_duration.as_millis() as u64;
-    return buffer_ms;
-}
+fn main() -> Result<(), WaveJumperError> {
+    // wave_jumper --screenshot ui.png
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(k) = args.iter().position(|a| a == "--screenshot") {
+        let path: &str = args.get(k + 1).map_or("wave_jumper.png", |p| p.as_str());
+        return save_screenshot(path);
+    }
 
-fn punch_file_into_sink(mf: &str, sink: &Sink) -> Result<(), Box<dyn Error>>{
-    let tape = get_decoded_wav(mf).build_looped()?;
-    sink.append(tape);
-    Ok(())
-}
+    // A broken config shouldn't stop the show, but we want to hear about it
+    let config: Config = Config::load(CONFIG_PATH).unwrap_or_else(|e| {
+        warn!("@Warning -- {}, using the defaults", e);
+        Config::default()
+    });
 
-fn get_ssd1306(i2c: I2c) -> Oled {
-    let interface = I2CDisplayInterface::new(i2c);
-    let oled = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0);
-    oled.into_buffered_graphics_mode()
-}
+    // wave_jumper --selftest or --calibrate, for bringing up a freshly wired board
+    let bring_up: Option<&str> = ["--selftest", "--calibrate"].into_iter().find(|f| args.iter().any(|a| a == f));
+
+    // wave_jumper --simulate, a breadboard and oled in the terminal
+    if args.iter().any(|a| a == "--simulate") {
+        let board = Breadboard::new();
+        let config: Config = Breadboard::wire_config(&config);
+        let gpio: MemGpio = board.gpio(&config);
+        let mut oled = Display::new(TermSurface::open(board)?);
+        if let Some(how) = bring_up {
+            // The virtual board is always wired the same, so there's no map worth keeping
+            let res = check_board(how, &gpio, &mut oled, &config, false);
+            if res.is_ok() {
+                // Leave the results up until we're told to quit
+                say!("@Done, q quits");
+                loop {
+                    sleep(Duration::from_secs(1));
+                }
+            }
+            restore_terminal();
+            return res;
+        }
+        let res = run(&gpio, oled, &Remote::new(), &config);
+        restore_terminal();
+        return res;
+    }
 
-fn get_dot_row(doty: i32, size: u32, pad: u32, num: usize) -> Vec<Dot> {
-    let mut dots: Vec<Dot> = Vec::new();
-    let mut dotx: i32 = 0; 
-    for _ in 0..num {
-        dotx = (dotx as u32 + pad) as i32;
-        dots.push(Dot{x: dotx, y: doty, sz: size, lv: DotLevel::Low});
+    if let Some(how) = bring_up {
+        return check_board(how, &RpiGpio, &mut Display::new(Panel::new()), &config, true);
     }
-    return dots;
-}
 
-fn clear_dot(oled: &mut Display, dot: &mut Dot) {
-    dot.lv = DotLevel::Low;
-    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Eraser));
-    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen));
-}
+    // wave_jumper --tui, for driving the instrument over ssh
+    let remote = Remote::new();
+    let with_tui: bool = args.iter().any(|a| a == "--tui");
+    if with_tui {
+        tui::open(remote.sender(), remote.status());
+    }
 
-fn fill_dot(oled: &mut Display, dot: &mut Dot) {
-    dot.lv = DotLevel::High;
-    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Marker));
-}
-    
-fn main() -> Result<(), Box<dyn Error>> {
     // Let's ensure we have a sink before we proceed
     let host = cpal::default_host();
     let device = host
         .default_output_device()
-        .expect("no audio devices.. gross!");
+        .ok_or_else(|| WaveJumperError::Audio("no audio devices.. gross!".into()))?;
     let dev_conf = device.default_output_config()?;
     let dev_name = device.name().unwrap_or_else(|_| "dead device _ do not use".into());
-    println!("@Device Name = {}", dev_name);
-    println!("@Device Config = {:?}", dev_conf);
-
-    // Number of chunks to split our song into.
-    const num_steps: usize = 16;
+    say!("@Device Name = {}", dev_name);
+    say!("@Device Config = {:?}", dev_conf);
 
-    /* Init the multiplexors to read user's input path for our tape */
-    // Construct demultiplexor
-    let counter_demux = Counter8::new([17, 27, 22]);
-    let mutrc_counter_demux = Rc::new(RefCell::new(counter_demux));
-    let demux_lsb = Mux8 {
-        s: Rc::clone(&mutrc_counter_demux),
-        z: None,
-        e: Some(get_digital_out(5)),
-    };
-    let demux_msb = Mux8 {
-        s: Rc::clone(&mutrc_counter_demux),
-        z: None,
-        e: Some(get_digital_out(6)),
-    };
-    // An array to store our demultiplexor
-    let mut demux: [Mux8; 2] = [demux_lsb, demux_msb];
-    for mx in demux.iter_mut() {
-        mx.e.as_mut().unwrap().set_high();
+    // A nice oled display for some user feedback, if there's one plugged in
+    let res = run(&RpiGpio, Display::new(Panel::new()), &remote, &config);
+    if with_tui {
+        tui::close();
     }
-    // Construct Input Mux
-    let counter_muxin = Counter8::new([21, 20, 16]);
-    let mutrc_counter_muxin = Rc::new(RefCell::new(counter_muxin));
-    let mux_lsb = Mux8 {
-        s: Rc::clone(&mutrc_counter_muxin),
-        z: Some(get_digital_generic(23, Mode::Input)),
-        e: None,
-    };
-    let mux_msb = Mux8 {
-        s: Rc::clone(&mutrc_counter_muxin),
-        z: Some(get_digital_generic(24, Mode::Input)),
-        e: None,
-    };
-    // Array to store our input multiplexors
-    let mut mux: [Mux8; 2] = [mux_msb, mux_lsb];
-    for mx in mux.iter_mut() {
-        mx.z.as_mut().unwrap().set_bias(Bias::PullDown);
-    }
-
-    // This array can collect and store the data received from every input mux
-    let mut mux_data: [Level; num_steps] = [Level::Low; num_steps];
-
-    // A nice oled display for some user feedback
-    let mut ssd1306 = Display::new(get_ssd1306(I2c::new()?));
+    res
+}
 
-    // Some UI decisions.
-    const title_ui_ycoord: i32 = 5;
-    const title_ui_ysize: u32 = 8;
-    const demux_ui_ycoord: i32 = (title_ui_ycoord as u32 + title_ui_ysize + 2) as i32;
-    const muxin_ui_ycoord: i32 = 55;
-    const line_ui_ystart: i32 = demux_ui_ycoord + 10;
-    const line_ui_yend: i32 = muxin_ui_ycoord - 5;
-    const dot_ui_size: u32 = 7;
-    const dot_ui_xpad: u32 = dot_ui_size;
+// The instrument itself, on whatever pins and display we're given
+fn run<S: Surface>(gpio: &dyn GpioBackend, oled: Display<S>, remote: &Remote, config: &Config) -> Result<(), WaveJumperError> {
+    /* Init the multiplexors to read user's input path for our tape */
+    let patch: Arc<PatchMatrix> = PatchBay::new(gpio, config)?.spawn();
 
-    // we need a to keep track of the connections.
-    let mut links: Vec<Link> = Vec::new();
+    // Buttons and an encoder to skip tapes and work the menu
+    // but we can still perform without them.
+    Inputs::new(gpio, config).spawn(config.input_poll, remote.sender());
+    let mut knob: Knob = config.encoder_knob;
 
-    // Some user input to skip to next song
-    let button : InputPin = get_digital_in(26);
-    let mut latch = Latch::Reset;
+    // Anything that talks OSC or MIDI can play with the speed too
+    if let Some(port) = config.osc_port {
+        grumble(osc::listen(port, remote.sender()));
+    }
+    // Held on to for as long as we play
+    let _midi = config.midi_in.as_deref().and_then(|name| match midi::listen(name, remote.sender()) {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            warn!("@Warning -- {}", e);
+            None
+        }
+    });
 
-    // Make a sink containing a loopable, seekable, and measured tape
-    let stream_handle = OutputStreamBuilder::open_default_stream()?;
+    // Make a sink we can feed decks of tape into, with as many outputs as we route to if the device has them
+    let mut settings: Settings = config.settings();
+    let stream_handle = match OutputStreamBuilder::from_default_device() {
+        Ok(builder) => builder.with_channels(settings.outputs).open_stream_or_fallback()?,
+        Err(_) => OutputStreamBuilder::open_default_stream()?,
+    };
+    let opened: u16 = stream_handle.config().channel_count();
+    if opened != settings.outputs {
+        warn!("@Warning -- asked for {} outputs and got {}, steps routed past the last wrap round to the first", settings.outputs, opened);
+        settings.outputs = opened;
+    }
     let sink = Sink::connect_new(stream_handle.mixer());
 
-    // Pull the wavs from ./assets/
-    let waves: Vec<String> = get_wav_from_local_assets()?;
-    let mut current_wav_idx: usize = 0;
-    let mut mf: &String = &waves[current_wav_idx];
-    let mut buffer_ms: u64 = get_wav_duration(get_decoded_wav(mf));
-
-    // Fill the sink with some sound!
-    punch_file_into_sink(mf, &sink)?;
+    // The jump engine, and everything the performer can change on it as we go
+    let mut engine = WaveJumper::new(settings);
+    let mut ui = Ui::new(oled);
 
-    // We need to calculate how we want to divi up our tape into seekable chuncks
-    const num_chunks : u32 = num_steps as u32;
-    let mut chunk_len: u64 = buffer_ms / num_chunks as u64;
-    let mut jump_to: Option<u64> = None;
-    let mut jump_to_ms: Option<u64> = None;
-    let mut position: u32 = 0;
+    // Pull the wavs and kits from ./assets/ and load the first one that plays
+    let mut shelf = Shelf::open(&mut ui, &sink, &mut engine);
+    ui.start(shelf.kit());
 
-    // feedback for selected song
-    ssd1306.text(5, title_ui_ycoord, mf);
-    // feedback for demux
-    let demux_dots: [Dot; num_steps] = get_dot_row(
-        demux_ui_ycoord,
-        dot_ui_size,
-        dot_ui_xpad,
-        num_steps
-    ).try_into().expect("Vec<Dot>'s len must equal chunk len");
-
-    for dot in demux_dots {
-        ssd1306.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen));
-    }
-    // feedback for inmux
-    let mut muxin_dots: [Dot; num_steps] = get_dot_row(
-        muxin_ui_ycoord,
-        dot_ui_size,
-        dot_ui_xpad,
-        num_steps
-    ).try_into().expect("Vec<Dot>'s len must equal chunk len");
-    for dot in muxin_dots {
-        ssd1306.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen));
-    }
+    // Things our controllers can ask of us between steps
+    // How many tapes to skip, backwards if negative
+    let mut skip: i32 = 0;
+    // Over to the bank in the settings, which might not be the one we started in
+    let mut switch_bank: bool = engine.settings.bank != shelf.bank();
+    let mut paused: bool = false;
 
     // lets go chaps
     sink.play();
@@ -223,140 +180,130 @@ fn main() -> Result<(), Box<dyn Error>> {
     loop {
         // We need to compensate for calculation time so let's take a Instant
         let epoch: Instant = Instant::now();
-        
-        // Check if we skip to next tape loop
-        if button.is_low() && latch == Latch::Reset {
-            // Set the latch to avoid double skipping
-            latch = Latch::Set;
-            // Clear sink of our current loop
-            sink.stop();
-            // Get the next song
-            current_wav_idx = (current_wav_idx + 1) % waves.len();
-            mf = &waves[current_wav_idx];
-            // We need to update the length of each sample to new track
-            buffer_ms = get_wav_duration(get_decoded_wav(mf));
-            chunk_len = buffer_ms / num_chunks as u64;
-            // start from the top
-            position = 0;
-            // Got a new file, let's play it
-            punch_file_into_sink(mf, &sink)?;
-            sink.play();
-
-            // Draw the music file path to screen
-            ssd1306.rect(0, 0, 128, title_ui_ysize, Some(Brush::Eraser));
-            ssd1306.text(5, 5, mf);
-        } else if latch == Latch::Set && button.is_high() {
-            // User has released the button
-            latch = Latch::Reset;
-        }
-        
-        // Clear dot from last time
-        let mut demux_dot: Dot = demux_dots[position as usize];
-        clear_dot(&mut ssd1306, &mut demux_dot);
-                
-        // Jump if required
-        if let Some(j) = jump_to_ms {
-            sink.try_seek(Duration::from_millis(j))?;
-        }
 
-        // Update our position
-        if let Some(j) = jump_to {
-            position = j as u32;
-        } else {
-            position = (position + 1) % num_chunks;
+        // Check if we skip to next tape loop, or over to another bank
+        if std::mem::take(&mut switch_bank) {
+            shelf.switch_bank(&mut ui, &sink, &mut engine);
+            ui.load(shelf.kit());
+        } else if skip != 0 {
+            shelf.skip(skip, &mut ui, &sink, &mut engine);
+            ui.load(shelf.kit());
         }
-               
-        // Throw our position onto the GPIO
-        let inv_position: u32 = num_chunks - position - 1;
-        let (w, i): (usize, u32) = ((inv_position / 8) as usize, inv_position % 8);
-        demux[w].s.borrow_mut().set(i)?;
-        demux[w].e.as_mut().unwrap().set_low();
+        skip = 0;
 
-        // Replace with new dot after ROR
-        demux_dot = demux_dots[position as usize];
-        fill_dot(&mut ssd1306, &mut demux_dot);
-        
-        // Scan all multiplexed inputs
-        for (k, mx) in mux.iter_mut().enumerate() {
-            for _ in 0..8 {
-                mx.s.borrow_mut().up();
-                sleep(Duration::from_micros(1000));
-                let reading: Level = mx.z.as_mut().unwrap().read();
-                let i: usize = (k * 8) + mx.s.borrow().idx as usize;
-                mux_data[i] = reading;
-            }
-        }
+        // Take last step's jump, or walk on, and pick where to go from here
+        let out: StepOutcome = engine.step(&patch);
+        let StepOutcome { position, mux_word, rows, chunk_len, volume, .. } = out;
+        // However loud the patch would have it this step
+        sink.set_volume(volume);
 
-        // We're done with our IO so we can diable the mux again.
-        demux[w].e.as_mut().unwrap().set_high();
-
-        // Update the state of our dots.
-        for (dot, &dat) in muxin_dots.iter_mut().zip(&mux_data) {
-            let cache: Dot = dot.clone();
-            dot.lv = DotLevel::from_gpio_level(&dat);
-            if *dot != cache {
-                match dot.lv {
-                    DotLevel::High => fill_dot(&mut ssd1306, dot),
-                    DotLevel::Low => clear_dot(&mut ssd1306, dot),
-                }
-            }
-        }
+        // Where the playhead is on the tape
+        let buffer_ms: u64 = engine.buffer_ms();
+        let tape_loc: u64 = engine.tape_ms();
+        let playhead: f32 = tape_loc as f32 / buffer_ms as f32;
+        ui.show_step(&out, engine.steps(), playhead, &engine.settings, shelf.banks());
 
-        // Clean up dead links for this step
-        let mut dead_links: Vec<Link> = Vec::new();
-        let mut live_links: Vec<Link> = Vec::new(); 
-        for link in links.iter().filter(|&lk| lk.a.same_tile(&demux_dot)) {
-            match muxin_dots.iter().find(|&dot| dot.same_tile(&link.b)) { 
-                Some(dot) => {
-                    if dot.is_low() { dead_links.push(*link); }
-                    if dot.is_high() { live_links.push(*link); }
-                },
-                None => dead_links.push(*link),
-            }
-        }
-        links.retain(|lk| {
-            if dead_links.contains(lk) {
-                ssd1306.line(lk.a.x, line_ui_ystart, lk.b.x, line_ui_yend, Some(Brush::Eraser));
-                false
-            } else { true }
-        });
-        links.extend(muxin_dots.iter().filter(|d| d.is_high()).filter_map(|d| {
-            let new_lk = Link { a: demux_dot, b: *d };
-            if live_links.contains(&new_lk) { None } else {
-                ssd1306.line(new_lk.a.x, line_ui_ystart, new_lk.b.x, line_ui_yend, None);
-                Some(new_lk)
-            }
-        }));
-
-        // Flatten the multiplexed input into a unsigned int.
-        let mux_word: u16 = mux_data.iter().enumerate()
-            .fold(0, | word, (i, &byte) | {
-                // let k : u16 = (u16::BITS - 1 - i as u32) as u16;
-                word | (byte as u16) << i
-        });
-    
-        // Calculate the longest path we can take between demux and mux
-        jump_to = get_bitidx_at_maxdelta(&position, &mux_word, num_chunks);
-
-        // Convert that to a jump position on our tape
-        jump_to_ms = match jump_to {
-            Some(k) => Some(k * chunk_len),
-            None => None,
-        };
-
-        // Paint any changes to the display
-        ssd1306.paint();
-        
         // Bit of feedback on the console.
-        let tape_loc: u64 = sink.get_pos().as_millis() as u64 % buffer_ms;
-        println!(
+        say!(
             "@{:08}ms -- x{:02} v{:016b}",
             tape_loc, position, mux_word
         );
+        // What we tell our controllers about
+        let status: Status = Status {
+            tape: shelf.kit().path.clone(),
+            tape_ms: tape_loc,
+            buffer_ms,
+            chunk_len,
+            position,
+            mux_word,
+            strategy: engine.settings.strategy,
+            volume: engine.settings.volume,
+            paused,
+            patch: rows,
+            flaky: patch.flaky(),
+            confidence: patch.confidence(),
+        };
+        remote.publish(&status);
 
-        // Sleep until we are ready to jump again.
+        // Take orders from the panel and our controllers until we are ready to jump again.
         let chunk_duration = Duration::from_millis(chunk_len);
-        let looptime_remaining: Duration = chunk_duration.saturating_sub(epoch.elapsed());
-        sleep(looptime_remaining);
+        loop {
+            let looptime_remaining: Duration = chunk_duration.saturating_sub(epoch.elapsed());
+            if !paused && looptime_remaining.is_zero() {
+                break;
+            }
+            // Paused we just wait on the next order, however long it takes
+            let timeout: Duration = if paused { PAUSED_WAIT } else { looptime_remaining };
+            let Some(cmd) = remote.wait(timeout) else { continue };
+
+            let mut resume: bool = false;
+            match cmd {
+                Command::Input(ev) if ui.menu_open() => match ui.work_menu(ev, &mut engine.settings, shelf.banks()) {
+                    Outcome::Changed(Setting::Bank) => switch_bank = engine.settings.bank != shelf.bank(),
+                    Outcome::Changed(_) => {
+                        sink.set_volume(engine.volume());
+                        engine.transport().set_crossfade(engine.crossfade_ms());
+                    }
+                    Outcome::Closed | Outcome::Stay => {}
+                },
+                Command::Input(ev) => match ev {
+                    InputEvent::Press(_, Press::Long) => {
+                        shelf.rescan();
+                        ui.open_menu();
+                    }
+                    InputEvent::Press(_, Press::Double) => {
+                        paused = !paused;
+                        resume = true;
+                    }
+                    InputEvent::Press(Button::Main, Press::Short) => skip += 1,
+                    InputEvent::Press(Button::Encoder, Press::Short) => {
+                        knob = knob.next();
+                        say!("@Encoder turns the {}", knob.name());
+                    }
+                    InputEvent::Turn(dir) => match knob {
+                        Knob::Volume => {
+                            engine.settings.turn_volume(dir);
+                            sink.set_volume(engine.volume());
+                        }
+                        Knob::Tempo => engine.settings.turn_tempo(dir, buffer_ms),
+                        Knob::Tape => skip += dir,
+                    },
+                },
+                Command::Skip => skip += 1,
+                Command::TogglePause => {
+                    paused = !paused;
+                    resume = true;
+                }
+                Command::NextStrategy => engine.settings.strategy = engine.settings.strategy.next(),
+                Command::Volume(dv) => {
+                    engine.settings.volume = (engine.settings.volume + dv).clamp(0.0, VOLUME_MAX);
+                    sink.set_volume(engine.volume());
+                }
+                Command::SetVolume(v) => {
+                    engine.settings.volume = v.clamp(0.0, VOLUME_MAX);
+                    sink.set_volume(engine.volume());
+                }
+                Command::Speed(speed) => engine.settings.set_speed(speed),
+                Command::StepSpeed(step, speed) => engine.settings.set_step_speed(step, speed),
+                Command::Stretch(on) => engine.settings.stretch = on,
+                Command::Fx(param, v) => engine.set_fx(param, v),
+                Command::Quit => {
+                    sink.stop();
+                    return Ok(());
+                }
+            }
+
+            if resume {
+                if paused { sink.pause() } else { sink.play() }
+                remote.publish(&Status { paused, volume: engine.settings.volume, strategy: engine.settings.strategy, ..status.clone() });
+            }
+            if ui.menu_open() {
+                ui.draw_menu(&engine.settings, shelf.banks());
+            }
+            // Don't wait for the step to finish, change tapes right now
+            if skip != 0 && config.skip_on == SkipOn::Press && !paused {
+                break;
+            }
+        }
     }
 }
@@ -0,0 +1,220 @@
+use crate::error::WaveJumperError;
+use crate::input::{Button, InputEvent, Press};
+use crate::oled::{Brush, Display, Surface};
+use crate::settings::{Setting, Settings};
+use crate::tape::bank_name;
+
+// Rows of text that fit under the menu's heading
+const MENU_ROWS: usize = 5;
+const menu_ui_xcoord: i32 = 2;
+const menu_ui_header: i32 = 8;
+const menu_ui_rule: i32 = 11;
+const menu_ui_ystart: i32 = 21;
+const menu_ui_ypad: i32 = 10;
+
+// How a menu gets told what to do, by button or anything else
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuEvent {
+    Next,
+    Prev,
+    Select,
+    Back,
+}
+
+impl MenuEvent {
+    // Tap to move along, hold to pick, double tap to back out
+    pub fn from_press(press: Press) -> Self {
+        match press {
+            Press::Short => MenuEvent::Next,
+            Press::Long => MenuEvent::Select,
+            Press::Double => MenuEvent::Back,
+        }
+    }
+
+    // The encoder scrolls, pushing it in picks
+    pub fn from_input(ev: InputEvent) -> Self {
+        match ev {
+            InputEvent::Press(Button::Main, press) => MenuEvent::from_press(press),
+            InputEvent::Press(Button::Encoder, Press::Double) => MenuEvent::Back,
+            InputEvent::Press(Button::Encoder, _) => MenuEvent::Select,
+            InputEvent::Turn(dir) if dir < 0 => MenuEvent::Prev,
+            InputEvent::Turn(_) => MenuEvent::Next,
+        }
+    }
+}
+
+// What came of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Stay,
+    Changed(Setting),
+    Closed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Page {
+    // Every setting and its value
+    List,
+    // One setting, changing live as we go. We keep what it was in case we back out.
+    Edit { setting: Setting, was: Settings },
+    // Are you sure?
+    Confirm { setting: Setting, was: Settings, yes: bool },
+}
+
+// A menu of settings on the oled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Menu {
+    page: Page,
+    cursor: usize,
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Menu::new()
+    }
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu { page: Page::List, cursor: 0 }
+    }
+
+    // Changing bank drops the tape we're playing, so we ask first
+    fn needs_confirm(setting: Setting) -> bool {
+        setting == Setting::Bank
+    }
+
+    pub fn handle(&mut self, ev: MenuEvent, settings: &mut Settings, banks: &[String]) -> Outcome {
+        let page: Page = self.page.clone();
+        match (page, ev) {
+            (Page::List, MenuEvent::Next) => {
+                self.cursor = (self.cursor + 1) % Setting::ALL.len();
+            }
+            (Page::List, MenuEvent::Prev) => {
+                self.cursor = (self.cursor + Setting::ALL.len() - 1) % Setting::ALL.len();
+            }
+            (Page::List, MenuEvent::Select) => {
+                self.page = Page::Edit { setting: Setting::ALL[self.cursor], was: settings.clone() };
+            }
+            (Page::List, MenuEvent::Back) => return Outcome::Closed,
+            (Page::Edit { setting, .. }, MenuEvent::Next) => {
+                settings.nudge(setting, 1, banks.len());
+                return Outcome::Changed(setting).live(setting);
+            }
+            (Page::Edit { setting, .. }, MenuEvent::Prev) => {
+                settings.nudge(setting, -1, banks.len());
+                return Outcome::Changed(setting).live(setting);
+            }
+            (Page::Edit { setting, was }, MenuEvent::Select) => {
+                if Menu::needs_confirm(setting) && *settings != was {
+                    self.page = Page::Confirm { setting, was, yes: false };
+                    return Outcome::Stay;
+                }
+                self.page = Page::List;
+                return Outcome::Changed(setting);
+            }
+            (Page::Edit { setting, was }, MenuEvent::Back)
+            | (Page::Confirm { setting, was, .. }, MenuEvent::Back) => {
+                *settings = was;
+                self.page = Page::List;
+                return Outcome::Changed(setting);
+            }
+            (Page::Confirm { setting, was, yes }, MenuEvent::Next | MenuEvent::Prev) => {
+                self.page = Page::Confirm { setting, was, yes: !yes };
+            }
+            (Page::Confirm { setting, was, yes }, MenuEvent::Select) => {
+                self.page = Page::List;
+                if !yes {
+                    *settings = was;
+                }
+                return Outcome::Changed(setting);
+            }
+        }
+        Outcome::Stay
+    }
+
+    pub fn draw<S: Surface>(&self, oled: &mut Display<S>, settings: &Settings, banks: &[String]) -> Result<(), WaveJumperError> {
+        oled.clear()?;
+        let row_y = |k: usize| menu_ui_ystart + k as i32 * menu_ui_ypad;
+        match &self.page {
+            Page::List => {
+                oled.text(menu_ui_xcoord, menu_ui_header, "menu")?;
+                // Scroll so the cursor is always on screen
+                let first: usize = self.cursor.saturating_sub(MENU_ROWS - 1);
+                for (k, setting) in Setting::ALL.iter().enumerate().skip(first).take(MENU_ROWS) {
+                    let marker: &str = if k == self.cursor { ">" } else { " " };
+                    let row: String = format!("{}{:<8}{:>11}", marker, setting.name(), settings.show(*setting, banks));
+                    oled.text(menu_ui_xcoord, row_y(k - first), &row)?;
+                }
+            }
+            Page::Edit { setting, .. } => {
+                oled.text(menu_ui_xcoord, menu_ui_header, setting.name())?;
+                oled.text(menu_ui_xcoord, row_y(1), &format!("> {}", settings.show(*setting, banks)))?;
+                oled.text(menu_ui_xcoord, row_y(4), "tap+ hold=ok 2x=undo")?;
+            }
+            Page::Confirm { setting, yes, .. } => {
+                oled.text(menu_ui_xcoord, menu_ui_header, &format!("{}?", setting.name()))?;
+                let name: &str = banks.get(settings.bank).map_or("?", |b| bank_name(b));
+                oled.text(menu_ui_xcoord, row_y(0), &format!("load {}", name))?;
+                let choice: &str = if *yes { " no  [yes]" } else { "[no]  yes " };
+                oled.text(menu_ui_xcoord, row_y(2), choice)?;
+                oled.text(menu_ui_xcoord, row_y(4), "tap=swap hold=ok")?;
+            }
+        }
+        oled.line(0, menu_ui_rule, 127, menu_ui_rule, Some(Brush::Pencil))
+    }
+}
+
+impl Outcome {
+    // Some settings only take effect once they're confirmed
+    fn live(self, setting: Setting) -> Self {
+        if Menu::needs_confirm(setting) { Outcome::Stay } else { self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn banks() -> Vec<String> {
+        vec!["./assets/".into(), "./assets/drums/".into()]
+    }
+
+    #[test]
+    fn edits_apply_live_and_undo() {
+        let mut menu = Menu::new();
+        let mut settings = Settings::default();
+        menu.handle(MenuEvent::Next, &mut settings, &banks());
+        menu.handle(MenuEvent::Select, &mut settings, &banks());
+        assert_eq!(menu.handle(MenuEvent::Next, &mut settings, &banks()), Outcome::Changed(Setting::Tempo));
+        assert!(settings.tempo.is_some());
+        menu.handle(MenuEvent::Back, &mut settings, &banks());
+        assert_eq!(settings, Settings::default());
+        assert_eq!(menu.handle(MenuEvent::Back, &mut settings, &banks()), Outcome::Closed);
+    }
+
+    #[test]
+    fn bank_waits_for_a_yes() {
+        let mut menu = Menu::new();
+        let mut settings = Settings::default();
+        menu.cursor = Setting::ALL.iter().position(|&s| s == Setting::Bank).unwrap();
+        menu.handle(MenuEvent::Select, &mut settings, &banks());
+        assert_eq!(menu.handle(MenuEvent::Next, &mut settings, &banks()), Outcome::Stay);
+        assert_eq!(menu.handle(MenuEvent::Select, &mut settings, &banks()), Outcome::Stay);
+        menu.handle(MenuEvent::Next, &mut settings, &banks());
+        assert_eq!(menu.handle(MenuEvent::Select, &mut settings, &banks()), Outcome::Changed(Setting::Bank));
+        assert_eq!(settings.bank, 1);
+    }
+
+    #[test]
+    fn menu_fits_the_oled() {
+        use crate::framebuffer::FrameBuffer;
+        let mut oled = Display::new(FrameBuffer::oled());
+        let mut menu = Menu::new();
+        let mut settings = Settings::default();
+        for _ in 0..Setting::ALL.len() {
+            menu.draw(&mut oled, &settings, &banks()).unwrap();
+            menu.handle(MenuEvent::Next, &mut settings, &banks());
+        }
+    }
+}
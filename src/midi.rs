@@ -0,0 +1,84 @@
+use midir::{MidiInput, MidiInputConnection};
+
+use std::sync::mpsc::Sender;
+
+use crate::control::Command;
+use crate::error::WaveJumperError;
+use crate::fx::FxParam;
+
+// The controllers we listen to, on any channel
+const CC_SPEED: u8 = 16;
+const CC_STRETCH: u8 = 17;
+// One after another for each step
+const CC_FIRST_STEP: u8 = 20;
+const CC_STATUS: u8 = 0xb0;
+// Effects go on the sound controllers, and reverb where General MIDI keeps it
+const CC_FX: [(u8, FxParam); 10] = [
+    (70, FxParam::Filter),
+    (71, FxParam::Resonance),
+    (72, FxParam::Room),
+    (73, FxParam::Bits),
+    (74, FxParam::Cutoff),
+    (75, FxParam::Delay),
+    (76, FxParam::Feedback),
+    (77, FxParam::DelayMix),
+    (78, FxParam::Downsample),
+    (91, FxParam::ReverbMix),
+];
+
+// Half way is the tape's own speed, and every 32 either side is an octave
+pub fn cc_speed(value: u8) -> f32 {
+    2f32.powf((value as f32 - 64.0) / 32.0)
+}
+
+// What a MIDI message asks of us, if anything
+pub fn command(msg: &[u8]) -> Option<Command> {
+    let [status, cc, value] = *msg else { return None };
+    if status & 0xf0 != CC_STATUS {
+        return None;
+    }
+    match cc {
+        CC_SPEED => Some(Command::Speed(cc_speed(value))),
+        CC_STRETCH => Some(Command::Stretch(value >= 64)),
+        _ if (CC_FIRST_STEP..CC_FIRST_STEP + 16).contains(&cc) => {
+            Some(Command::StepSpeed((cc - CC_FIRST_STEP) as u32, cc_speed(value)))
+        }
+        _ => CC_FX.iter().find(|(fx_cc, _)| *fx_cc == cc).map(|&(_, param)| Command::Fx(param, value as f32 / 127.0)),
+    }
+}
+
+// Take orders from the first MIDI input with `name` in its name.
+// They only come while the connection is held on to.
+pub fn listen(name: &str, sender: Sender<Command>) -> Result<MidiInputConnection<()>, WaveJumperError> {
+    let midi_err = |e: String| WaveJumperError::Control(format!("midi: {}", e));
+    let input = MidiInput::new("wave_jumper").map_err(|e| midi_err(e.to_string()))?;
+    let port = input.ports().into_iter()
+        .find(|p| input.port_name(p).is_ok_and(|n| n.contains(name)))
+        .ok_or_else(|| midi_err(format!("no input called {}", name)))?;
+    let port_name: String = input.port_name(&port).unwrap_or_else(|_| name.into());
+    let conn = input.connect(&port, "wave_jumper", move |_, msg, _| {
+        if let Some(cmd) = command(msg) {
+            // The instrument's gone, nothing to do about it from here
+            let _ = sender.send(cmd);
+        }
+    }, ()).map_err(|e| midi_err(e.to_string()))?;
+    say!("@Listening for MIDI on {}", port_name);
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controllers_set_the_speed() {
+        assert_eq!(command(&[0xb3, CC_SPEED, 64]), Some(Command::Speed(1.0)));
+        assert_eq!(command(&[0xb0, CC_SPEED, 0]), Some(Command::Speed(0.25)));
+        assert_eq!(command(&[0xb0, CC_FIRST_STEP + 2, 96]), Some(Command::StepSpeed(2, 2.0)));
+        assert_eq!(command(&[0xb0, CC_STRETCH, 127]), Some(Command::Stretch(true)));
+        assert_eq!(command(&[0xb0, 74, 127]), Some(Command::Fx(FxParam::Cutoff, 1.0)));
+        // Notes and anything else we don't map
+        assert_eq!(command(&[0x90, CC_SPEED, 64]), None);
+        assert_eq!(command(&[0xb0, 7, 64]), None);
+    }
+}
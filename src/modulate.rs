@@ -0,0 +1,208 @@
+use crate::fx::{FxParam, FxParams};
+use crate::jump::get_bitidx_at_maxdelta;
+use crate::settings::{SPEED_RANGE, VOLUME_MAX};
+
+// Most wires out of one step we tell apart, any more is as much as it gets
+const MOD_MAX_WIRES: u32 = 4;
+// Longest crossfade a route can add
+const MOD_CROSSFADE_MS: f32 = 100.0;
+
+// What the patch says about the step we're on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatchReading {
+    pub position: u32,
+    // The wires out of it, anywhere we could jump
+    pub mux_word: u16,
+    pub steps: u32,
+    // The step a jump brought us here from, None if we walked on from the step before
+    pub jumped_from: Option<u32>,
+}
+
+// Something the patch says about the step we're on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModSource {
+    // How many wires come out of it
+    Wires,
+    // How far away the furthest of them goes
+    Distance,
+    // Whether the tape walked on from the step before rather than jumping here
+    Walked,
+    // Whether the step is wired to itself, or a jump brought us back to where we were
+    Stayed,
+}
+
+impl ModSource {
+    pub const ALL: [ModSource; 4] = [ModSource::Wires, ModSource::Distance, ModSource::Walked, ModSource::Stayed];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModSource::Wires => "wires",
+            ModSource::Distance => "distance",
+            ModSource::Walked => "walked",
+            ModSource::Stayed => "stayed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ModSource::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    // From nothing at 0 to as much as there can be at 1
+    pub fn read(self, patch: &PatchReading) -> f32 {
+        let PatchReading { position, mux_word, steps, jumped_from } = *patch;
+        let steps: u32 = steps.max(1);
+        let wired_to_itself: bool = mux_word.checked_shr(position).is_some_and(|w| w & 1 == 1);
+        let word: u16 = mux_word & !(1u16.checked_shl(position).unwrap_or(0));
+        let word: u16 = if steps < 16 { word & ((1 << steps) - 1) } else { word };
+        match self {
+            ModSource::Wires => word.count_ones().min(MOD_MAX_WIRES) as f32 / MOD_MAX_WIRES as f32,
+            ModSource::Distance => get_bitidx_at_maxdelta(&position, &word, steps).map_or(0.0, |to| {
+                let ahead: u32 = (to as u32 + steps - position) % steps;
+                ahead.min(steps - ahead) as f32 / (steps / 2).max(1) as f32
+            }),
+            ModSource::Walked => jumped_from.is_none() as u32 as f32,
+            ModSource::Stayed => (wired_to_itself || jumped_from == Some(position)) as u32 as f32,
+        }
+    }
+}
+
+// Anything a route can turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModTarget {
+    Fx(FxParam),
+    Volume,
+    Speed,
+    Crossfade,
+}
+
+impl ModTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            ModTarget::Fx(param) => param.name(),
+            ModTarget::Volume => "volume",
+            ModTarget::Speed => "speed",
+            ModTarget::Crossfade => "crossfade",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "volume" => Some(ModTarget::Volume),
+            "speed" => Some(ModTarget::Speed),
+            "crossfade" => Some(ModTarget::Crossfade),
+            _ => FxParam::from_name(name).map(ModTarget::Fx),
+        }
+    }
+}
+
+// Everything the routes can turn on a step, before and after they turn it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modulated {
+    pub fx: FxParams,
+    pub volume: f32,
+    pub speed: f32,
+    pub crossfade_ms: u32,
+}
+
+impl Modulated {
+    // Push `target` by `amount`, which at full depth is as far as it sensibly goes
+    fn turn(&mut self, target: ModTarget, amount: f32) {
+        match target {
+            ModTarget::Fx(param) => self.fx.set(param, self.fx.get(param) + amount),
+            ModTarget::Volume => self.volume = (self.volume + amount).clamp(0.0, VOLUME_MAX),
+            // An octave either way
+            ModTarget::Speed => self.speed = (self.speed * 2f32.powf(amount)).clamp(SPEED_RANGE.0, SPEED_RANGE.1),
+            ModTarget::Crossfade => {
+                self.crossfade_ms = (self.crossfade_ms as f32 + amount * MOD_CROSSFADE_MS).clamp(0.0, MOD_CROSSFADE_MS) as u32;
+            }
+        }
+    }
+}
+
+// One patch cord from what the patch says to something we can turn, as far as `depth` turns it either way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModRoute {
+    pub source: ModSource,
+    pub target: ModTarget,
+    pub depth: f32,
+}
+
+impl ModRoute {
+    // `wires cutoff 0.5`, the depth is optional
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        let (source, target, depth) = match words[..] {
+            [source, target] => (source, target, "1"),
+            [source, target, depth] => (source, target, depth),
+            _ => return Err(format!("expected `source target [depth]`, got `{}`", value)),
+        };
+        Ok(ModRoute {
+            source: ModSource::from_name(source).ok_or_else(|| format!("{} isn't something the patch can say", source))?,
+            target: ModTarget::from_name(target).ok_or_else(|| format!("{} isn't anything we can turn", target))?,
+            depth: depth.parse::<f32>().ok().filter(|d| (-1.0..=1.0).contains(d))
+                .ok_or_else(|| format!("{} isn't a depth from -1 to 1", depth))?,
+        })
+    }
+}
+
+// Everything as set, turned by every route on what the patch says about this step
+pub fn modulate(base: Modulated, routes: &[ModRoute], patch: &PatchReading) -> Modulated {
+    let mut out: Modulated = base;
+    for route in routes {
+        out.turn(route.target, route.source.read(patch) * route.depth);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(position: u32, mux_word: u16, steps: u32) -> PatchReading {
+        PatchReading { position, mux_word, steps, jumped_from: None }
+    }
+
+    fn base() -> Modulated {
+        Modulated { fx: FxParams::default(), volume: 1.0, speed: 1.0, crossfade_ms: 10 }
+    }
+
+    #[test]
+    fn reads_the_patch() {
+        // Step 2 wired to itself, 3 and 10
+        let word: u16 = (1 << 2) | (1 << 3) | (1 << 10);
+        assert_eq!(ModSource::Wires.read(&reading(2, word, 16)), 0.5);
+        assert_eq!(ModSource::Distance.read(&reading(2, word, 16)), 1.0);
+        assert_eq!(ModSource::Distance.read(&reading(2, 1 << 3, 16)), 0.125);
+        // Holes past the last step aren't wired anywhere we'd go
+        assert_eq!(ModSource::Wires.read(&reading(2, word, 8)), 0.25);
+        assert_eq!(ModSource::Distance.read(&reading(2, 0, 16)), 0.0);
+        assert_eq!(ModSource::Walked.read(&reading(2, 0, 16)), 1.0);
+        assert_eq!(ModSource::Walked.read(&PatchReading { jumped_from: Some(9), ..reading(2, 0, 16) }), 0.0);
+        // Staying put takes a wire back into the same step, walking on isn't it
+        assert_eq!(ModSource::Stayed.read(&reading(2, word, 16)), 1.0);
+        assert_eq!(ModSource::Stayed.read(&reading(4, word, 16)), 0.0);
+        assert_eq!(ModSource::Stayed.read(&PatchReading { jumped_from: Some(2), ..reading(2, 0, 16) }), 1.0);
+    }
+
+    #[test]
+    fn routes_turn_the_knobs() {
+        let route = ModRoute::parse("wires reverb_mix 0.4").unwrap();
+        let out: Modulated = modulate(base(), &[route], &reading(0, 0b1110, 16));
+        assert!((out.fx.reverb_mix - 0.3).abs() < 1e-6);
+        assert_eq!(ModRoute::parse("distance cutoff").unwrap().depth, 1.0);
+        assert!(ModRoute::parse("wires colour").is_err());
+        assert!(ModRoute::parse("wires cutoff 2").is_err());
+    }
+
+    #[test]
+    fn routes_reach_past_the_effects() {
+        let routes: Vec<ModRoute> = ["distance speed", "walked volume -0.5", "wires crossfade 0.5", "walked crossfade -1"]
+            .iter().map(|r| ModRoute::parse(r).unwrap()).collect();
+        // Jumped here from across the board, so it's an octave up and as loud as ever
+        let out: Modulated = modulate(base(), &routes, &PatchReading { jumped_from: Some(8), ..reading(0, 1 << 8, 16) });
+        assert_eq!((out.speed, out.volume, out.crossfade_ms), (2.0, 1.0, 22));
+        // Walked on with no wires, half as loud and no crossfade left
+        let out: Modulated = modulate(base(), &routes, &reading(0, 0, 16));
+        assert_eq!((out.speed, out.volume, out.crossfade_ms), (1.0, 0.5, 0));
+    }
+}
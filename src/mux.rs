@@ -1,37 +1,43 @@
+use crate::config::Config;
+use crate::error::WaveJumperError;
+use crate::patch::{ChannelMap, PatchMatrix};
 use crate::pinio::*;
 
-#[allow(unused_imports)]
-use rpi_pal::gpio::{InputPin, IoPin, OutputPin};
-use rpi_pal::gpio::Level;
+use rpi_pal::gpio::{Bias, Level};
 
-use std::rc::Rc;
-use std::cell::RefCell;
-use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Where the multiplexors are wired on the header
+pub const DEMUX_SELECT_PINS: [u8; 3] = [17, 27, 22];
+pub const DEMUX_ENABLE_PINS: [u8; 2] = [5, 6];
+pub const MUX_SELECT_PINS: [u8; 3] = [21, 20, 16];
+// Ordered as we read them, msb chip first
+pub const MUX_Z_PINS: [u8; 2] = [24, 23];
 
 // 3 bit binary counter
 pub type Counter8 = Counter<3>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Latch {Set, Reset}
-
 // Binary counter that punches onto GPIO
-#[derive(Debug)]
 pub struct Counter<const BITS: usize> {
     pub idx: u32,
-    pins: [OutputPin; BITS],
+    pins: [Box<dyn DigitalOut>; BITS],
 }
 
 impl<const BITS: usize> Counter<BITS> {
-    pub fn new(gpio_nums: [u8; BITS]) -> Self {
-        let mut outs: Vec<OutputPin> = Vec::new();
+    pub fn new(gpio: &dyn GpioBackend, gpio_nums: [u8; BITS]) -> Result<Self, WaveJumperError> {
+        let mut outs: Vec<Box<dyn DigitalOut>> = Vec::new();
         for i in gpio_nums {
-            outs.push(get_digital_out(i));
+            outs.push(gpio.output(i)?);
         }
-        let pin_outs: [OutputPin; BITS] = outs.try_into().unwrap();
-        Self {
+        let pin_outs: [Box<dyn DigitalOut>; BITS] = outs.try_into()
+            .map_err(|_| WaveJumperError::Patch("counter lost some of its select lines".into()))?;
+        Ok(Self {
             idx: (1 << BITS) - 1,
             pins: pin_outs,
-        }
+        })
     }
 
     fn out(&mut self) {
@@ -47,9 +53,11 @@ impl<const BITS: usize> Counter<BITS> {
         self.out();
     }
 
-    pub fn set(&mut self, idx: u32) -> Result<(), Box<dyn Error>> {
+    pub fn set(&mut self, idx: u32) -> Result<(), WaveJumperError> {
         if idx >= (1 << BITS) {
-            return Err("The provided index is unexpressible by this counter".into());
+            return Err(WaveJumperError::Patch(format!(
+                "channel {} is unexpressible by a {} bit counter", idx, BITS
+            )));
         }
         self.idx = idx;
         self.out();
@@ -58,9 +66,276 @@ impl<const BITS: usize> Counter<BITS> {
 }
 
 // Models a 74HC4051 multiplexor
-#[derive(Debug)]
 pub struct Mux8 {
-    pub s: Rc<RefCell<Counter8>>,
-    pub z: Option<IoPin>,
-    pub e: Option<OutputPin>,
+    pub s: Arc<Mutex<Counter8>>,
+    pub z: Option<Box<dyn DigitalIn>>,
+    pub e: Option<Box<dyn DigitalOut>>,
+}
+
+// The pair of demultiplexors and pair of multiplexors our jumper wires run between
+pub struct PatchBay {
+    demux: [Mux8; 2],
+    mux: [Mux8; 2],
+    // How long a mux channel gets to settle before we read it
+    settle: Duration,
+    // Reads per channel, the majority wins
+    samples: u32,
+    // Scans a wire has to agree on before we believe it changed
+    hysteresis: u32,
+    map: ChannelMap,
+}
+
+impl PatchBay {
+    pub fn new(gpio: &dyn GpioBackend, config: &Config) -> Result<Self, WaveJumperError> {
+        // Construct demultiplexor
+        let counter_demux = Counter8::new(gpio, DEMUX_SELECT_PINS)?;
+        let mutrc_counter_demux = Arc::new(Mutex::new(counter_demux));
+        let demux_lsb = Mux8 {
+            s: Arc::clone(&mutrc_counter_demux),
+            z: None,
+            e: Some(gpio.output(DEMUX_ENABLE_PINS[0])?),
+        };
+        let demux_msb = Mux8 {
+            s: Arc::clone(&mutrc_counter_demux),
+            z: None,
+            e: Some(gpio.output(DEMUX_ENABLE_PINS[1])?),
+        };
+        // An array to store our demultiplexor
+        let mut demux: [Mux8; 2] = [demux_lsb, demux_msb];
+        for mx in demux.iter_mut() {
+            if let Some(e) = mx.e.as_mut() { e.set_high(); }
+        }
+        // Construct Input Mux
+        let counter_muxin = Counter8::new(gpio, MUX_SELECT_PINS)?;
+        let mutrc_counter_muxin = Arc::new(Mutex::new(counter_muxin));
+        let mux_msb = Mux8 {
+            s: Arc::clone(&mutrc_counter_muxin),
+            z: Some(gpio.input(MUX_Z_PINS[0], Bias::PullDown)?),
+            e: None,
+        };
+        let mux_lsb = Mux8 {
+            s: Arc::clone(&mutrc_counter_muxin),
+            z: Some(gpio.input(MUX_Z_PINS[1], Bias::PullDown)?),
+            e: None,
+        };
+        // Array to store our input multiplexors
+        let mux: [Mux8; 2] = [mux_msb, mux_lsb];
+        Ok(PatchBay {
+            demux,
+            mux,
+            settle: config.mux_settle,
+            samples: config.mux_samples.max(1),
+            hysteresis: config.wire_hysteresis.max(1),
+            map: config.channel_map,
+        })
+    }
+
+    // Drive the demux channel for `position` and read back every mux channel as a word
+    pub fn scan(&mut self, position: u32) -> Result<u16, WaveJumperError> {
+        let (w, i): (usize, u32) = self.map.demux_channel(position);
+        self.drive(w, i)
+    }
+
+    // Drive one channel of one demux chip, whichever hole it's wired to, and read back every mux channel
+    pub fn drive(&mut self, w: usize, i: u32) -> Result<u16, WaveJumperError> {
+        // Throw our position onto the GPIO
+        self.demux[w].s.lock().unwrap_or_else(|e| e.into_inner()).set(i)?;
+        if let Some(e) = self.demux[w].e.as_mut() { e.set_low(); }
+
+        let mux_word: u16 = self.read_mux();
+
+        // We're done with our IO so we can diable the mux again.
+        if let Some(e) = self.demux[w].e.as_mut() { e.set_high(); }
+        Ok(mux_word)
+    }
+
+    // Read every mux channel with nothing driven, anything high here is stuck that way
+    pub fn scan_idle(&mut self) -> u16 {
+        self.read_mux()
+    }
+
+    // Open the mux inputs again with another pull, with a pull up anything reading low is tied that way
+    pub fn pull(&mut self, gpio: &dyn GpioBackend, bias: Bias) -> Result<(), WaveJumperError> {
+        for (mx, pin) in self.mux.iter_mut().zip(MUX_Z_PINS) {
+            // Let go of the pin before we ask for it back
+            mx.z = None;
+            mx.z = Some(gpio.input(pin, bias)?);
+        }
+        Ok(())
+    }
+
+    fn read_mux(&mut self) -> u16 {
+        // Both muxes hang off the same select lines, so we read them side by side
+        let mut mux_word: u16 = 0;
+        let mut select = self.mux[0].s.lock().unwrap_or_else(|e| e.into_inner());
+        for _ in 0..8 {
+            select.up();
+            thread::sleep(self.settle);
+            for (k, mx) in self.mux.iter().enumerate() {
+                let highs: u32 = (0..self.samples)
+                    .filter(|_| mx.z.as_ref().is_some_and(|z| z.read() == Level::High))
+                    .count() as u32;
+                let hole: Option<u32> = self.map.mux_hole(k, select.idx);
+                mux_word |= hole.map_or(0, |h| ((highs * 2 > self.samples) as u16) << h);
+            }
+        }
+        mux_word
+    }
+}
+
+// How long the scanner waits after a failed scan, doubling each time it fails again
+const SCAN_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const SCAN_BACKOFF_MAX: Duration = Duration::from_secs(1);
+// Failed scans in a row between repeats of the warning
+const SCAN_WARN_EVERY: u32 = 60;
+// The least rest between sweeps, for boards that need no settle time
+const SCAN_REST_MIN: Duration = Duration::from_millis(1);
+
+fn scan_backoff(failures: u32) -> Duration {
+    SCAN_BACKOFF_MIN.saturating_mul(1 << failures.saturating_sub(1).min(16)).min(SCAN_BACKOFF_MAX)
+}
+
+// How much one flicker knocks off a wire's confidence
+const FLICKER_COST: f32 = 0.1;
+// Below this a contact is intermittent, and it has to get back above the other to be trusted again
+const FLAKY_BELOW: f32 = 0.6;
+const STEADY_ABOVE: f32 = 0.9;
+
+// One hole to hole connection as seen over many scans
+#[derive(Debug, Clone, Copy)]
+pub struct WireFilter {
+    connected: bool,
+    last: bool,
+    // Scans in a row that disagree with `connected`
+    streak: u32,
+    // 1 for a wire that reads the same every time, falling each time it flickers
+    confidence: f32,
+    flaky: bool,
+}
+
+impl Default for WireFilter {
+    fn default() -> Self {
+        WireFilter { connected: false, last: false, streak: 0, confidence: 1.0, flaky: false }
+    }
+}
+
+impl WireFilter {
+    pub fn update(&mut self, raw: bool, hysteresis: u32) -> bool {
+        let flicker: f32 = (raw != self.last) as u8 as f32;
+        self.confidence = self.confidence * (1.0 - FLICKER_COST) + (1.0 - flicker) * FLICKER_COST;
+        self.last = raw;
+        self.streak = if raw == self.connected { 0 } else { self.streak + 1 };
+        if self.streak >= hysteresis {
+            self.connected = raw;
+            self.streak = 0;
+        }
+        if self.confidence < FLAKY_BELOW {
+            self.flaky = true;
+        } else if self.confidence > STEADY_ABOVE {
+            self.flaky = false;
+        }
+        self.connected
+    }
+
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    pub fn is_flaky(&self) -> bool {
+        self.flaky
+    }
+}
+
+impl PatchBay {
+    // Scan on a thread of its own so the step clock never waits on the gpio
+    pub fn spawn(mut self) -> Arc<PatchMatrix> {
+        let matrix = Arc::new(PatchMatrix::from_rows([0; 16]));
+        let mut filters = [[WireFilter::default(); 16]; 16];
+        let published = Arc::clone(&matrix);
+        // The rest of the board gets swept a row at a time
+        let mut sweep: u32 = 0;
+        // Scans in a row that came back with an error
+        let mut failures: u32 = 0;
+        thread::spawn(move || loop {
+            // Our step, then every step we could land on next so it's fresh when we get there
+            let position: u32 = published.position.load(Ordering::Relaxed);
+            let next: u16 = published.row(position) | 1 << ((position + 1) % 16);
+            let ahead = (0..16).filter(|&i| i != position && next >> i & 1 == 1);
+            sweep = (sweep + 1) % 16;
+            let behind = Some(sweep).filter(|&i| i != position && next >> i & 1 == 0);
+            for row in std::iter::once(position).chain(ahead).chain(behind) {
+                let raw: u16 = match self.scan(row) {
+                    Ok(word) => word,
+                    Err(e) => {
+                        // The board's gone away, so give it a rest rather than spin on it
+                        if failures.is_multiple_of(SCAN_WARN_EVERY) {
+                            warn!("@Warning -- {} (failed {} scans in a row)", e, failures + 1);
+                        }
+                        failures += 1;
+                        thread::sleep(scan_backoff(failures));
+                        break;
+                    }
+                };
+                if failures > 0 {
+                    say!("@Patch bay is back after {} failed scans", failures);
+                    failures = 0;
+                }
+                let (mut word, mut flaky): (u16, u16) = (0, 0);
+                for (k, filter) in filters[row as usize].iter_mut().enumerate() {
+                    let was_flaky: bool = filter.is_flaky();
+                    word |= (filter.update(raw >> k & 1 == 1, self.hysteresis) as u16) << k;
+                    flaky |= (filter.is_flaky() as u16) << k;
+                    if filter.is_flaky() && !was_flaky {
+                        warn!("@Warning -- the wire from x{:02} to {} is coming and going", row, k);
+                    }
+                    let percent: u8 = (filter.confidence() * 100.0).round() as u8;
+                    published.confidence[row as usize][k].store(percent, Ordering::Relaxed);
+                }
+                published.rows[row as usize].store(word, Ordering::Relaxed);
+                published.flaky[row as usize].store(flaky, Ordering::Relaxed);
+            }
+            // Rest a settle's worth between sweeps rather than hog a core
+            thread::sleep(self.settle.max(SCAN_REST_MIN));
+        });
+        matrix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_needs_a_few_scans_to_change() {
+        let mut wire = WireFilter::default();
+        assert!(!wire.update(true, 2));
+        assert!(wire.update(true, 2));
+        // One bad read isn't enough to pull it
+        assert!(wire.update(false, 2));
+        assert!(wire.update(true, 2));
+        assert!(!wire.is_flaky());
+    }
+
+    #[test]
+    fn failed_scans_back_off() {
+        assert_eq!(scan_backoff(1), SCAN_BACKOFF_MIN);
+        assert_eq!(scan_backoff(2), SCAN_BACKOFF_MIN * 2);
+        assert_eq!(scan_backoff(8), SCAN_BACKOFF_MAX);
+        assert_eq!(scan_backoff(u32::MAX), SCAN_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn loose_wire_loses_confidence() {
+        let mut wire = WireFilter::default();
+        for k in 0..20 {
+            wire.update(k % 2 == 0, 2);
+        }
+        assert!(wire.is_flaky());
+        assert!(wire.confidence() < FLAKY_BELOW);
+        // Push it back in properly and it comes good
+        for _ in 0..40 {
+            wire.update(true, 2);
+        }
+        assert!(!wire.is_flaky());
+    }
 }
@@ -1,6 +1,6 @@
 use ssd1306::mode::BufferedGraphicsMode;
 use ssd1306::prelude::*;
-use ssd1306::Ssd1306;
+use ssd1306::{Ssd1306, I2CDisplayInterface};
 
 use embedded_graphics::mono_font::{ascii::FONT_6X10, MonoTextStyle};
 use embedded_graphics::pixelcolor::BinaryColor;
@@ -11,6 +11,10 @@ use embedded_graphics::text::Text;
 
 use rpi_pal::i2c::I2c;
 
+use std::time::{Duration, Instant};
+
+use crate::error::{display_err, WaveJumperError};
+
 // 128×64 I²C OLED in buffered-graphics mode
 pub type Oled = Ssd1306<I2CInterface<I2c>, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
 
@@ -46,84 +50,219 @@ impl Brush {
     }
 }
 
-// Models the SSD1306 oled display
-pub struct Display {
-    oled: Oled,
+// Anything the Display can draw onto and push out
+pub trait Surface: DrawTarget<Color = BinaryColor, Error: std::fmt::Debug> + OriginDimensions {
+    fn flush(&mut self) -> Result<(), WaveJumperError>;
+
+    // Surfaces that can come and go report true when they come back
+    fn reattach(&mut self) -> bool {
+        false
+    }
+
+    fn is_attached(&self) -> bool {
+        true
+    }
+}
+
+// The oled is optional, so we only go looking for one every so often
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+// Try to bring up an SSD1306 on the default i2c bus
+fn probe_ssd1306() -> Result<Oled, WaveJumperError> {
+    let interface = I2CDisplayInterface::new(I2c::new()?);
+    let mut disp = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+        .into_buffered_graphics_mode();
+    // Nobody answering on the bus shows up as a failed init
+    disp.init().map_err(display_err)?;
+    disp.clear(BinaryColor::Off).map_err(display_err)?;
+    disp.flush().map_err(display_err)?;
+    Ok(disp)
+}
+
+// A hot-pluggable SSD1306, drawing to nowhere while it's missing
+pub struct Panel {
+    oled: Option<Oled>,
+    last_probe: Instant,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Panel::new()
+    }
+}
+
+impl Panel {
+    // Never fails, without an oled we run headless
+    pub fn new() -> Self {
+        let oled: Option<Oled> = probe_ssd1306()
+            .map_err(|e| warn!("@No oled, running headless -- {}", e))
+            .ok();
+
+        Panel {
+            oled,
+            last_probe: Instant::now(),
+        }
+    }
+}
+
+impl OriginDimensions for Panel {
+    fn size(&self) -> Size {
+        Size::new(DisplaySize128x64::WIDTH as u32, DisplaySize128x64::HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Panel {
+    type Color = BinaryColor;
+    type Error = WaveJumperError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        match self.oled.as_mut() {
+            Some(oled) => oled.draw_iter(pixels).map_err(display_err),
+            None => Ok(()),
+        }
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        match self.oled.as_mut() {
+            Some(oled) => oled.clear(color).map_err(display_err),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Surface for Panel {
+    // A failed flush means the oled has gone walkabout, so we let it go.
+    fn flush(&mut self) -> Result<(), WaveJumperError> {
+        let Some(oled) = self.oled.as_mut() else { return Ok(()) };
+        let res = oled.flush().map_err(display_err);
+        if res.is_err() {
+            self.oled = None;
+            self.last_probe = Instant::now();
+        }
+        res
+    }
+
+    // Periodically look for an oled that has been plugged in.
+    fn reattach(&mut self) -> bool {
+        if self.oled.is_some() || self.last_probe.elapsed() < PROBE_INTERVAL {
+            return false;
+        }
+        self.last_probe = Instant::now();
+        self.oled = probe_ssd1306().ok();
+        if self.oled.is_some() {
+            say!("@Oled attached");
+        }
+        self.oled.is_some()
+    }
+
+    fn is_attached(&self) -> bool {
+        self.oled.is_some()
+    }
+}
+
+// Models the oled display on top of any binary Surface
+pub struct Display<S: Surface = Panel> {
+    surface: S,
     default_brush : Brush,
 }
 
-impl Display {    
+impl<S: Surface> Display<S> {    
     
-    pub fn new(mut disp: Oled) -> Self {
-        disp.init().unwrap();
-        disp.clear(BinaryColor::Off).unwrap();
-        disp.flush().unwrap();
-
+    pub fn new(surface: S) -> Self {
         Display {
-            oled: disp,
+            surface,
             default_brush: Brush::Pencil,
         }
     }
 
-    pub fn rect(&mut self, x: i32, y: i32, a: u32, b: u32, brush: Option<Brush>) {
+    #[allow(dead_code)]
+    pub fn is_attached(&self) -> bool {
+        self.surface.is_attached()
+    }
+
+    #[allow(dead_code)]
+    pub fn surface(&self) -> &S {
+        &self.surface
+    }
+
+    // Returns true when the surface turns back up, the caller should then redraw everything.
+    pub fn reattach(&mut self) -> bool {
+        self.surface.reattach()
+    }
+
+    pub fn rect(&mut self, x: i32, y: i32, a: u32, b: u32, brush: Option<Brush>) -> Result<(), WaveJumperError> {
         let draw_point = Point::new(x, y);
-        assert!(self.point_in_range(draw_point));
+        self.check_in_range(draw_point)?;
         let size: Size = Size::new(a, b);
 
         let style: PrimitiveStyle<BinaryColor> = brush.unwrap_or(self.default_brush).style();
 
         let rect = Rectangle::new(draw_point, size);
-        rect.into_styled(style).draw(&mut self.oled).unwrap();
+        rect.into_styled(style).draw(&mut self.surface).map_err(display_err)
     }
 
     #[allow(dead_code)]
-    pub fn circle(&mut self, x: i32, y: i32, sz: u32, brush: Option<Brush>) {
+    pub fn circle(&mut self, x: i32, y: i32, sz: u32, brush: Option<Brush>) -> Result<(), WaveJumperError> {
         let draw_point = Point::new(x, y);
-        assert!(self.point_in_range(draw_point));
+        self.check_in_range(draw_point)?;
 
         let style: PrimitiveStyle<BinaryColor> = brush.unwrap_or(self.default_brush).style();
 
         let circle = Circle::new(draw_point, sz);
-        circle.into_styled(style).draw(&mut self.oled).unwrap();
+        circle.into_styled(style).draw(&mut self.surface).map_err(display_err)
     }
 
     #[allow(dead_code)]
-    pub fn line(&mut self, x: i32, y: i32, v: i32, w: i32, brush: Option<Brush>) {
+    pub fn line(&mut self, x: i32, y: i32, v: i32, w: i32, brush: Option<Brush>) -> Result<(), WaveJumperError> {
         let start = Point::new(x, y);
         let end = Point::new(v, w);
-        assert!(self.point_in_range(start) && self.point_in_range(end));
+        self.check_in_range(start)?;
+        self.check_in_range(end)?;
         
         let style: PrimitiveStyle<BinaryColor> = brush.unwrap_or(self.default_brush).stroke_style();
 
         let line = Line::new(start, end);
-        line.into_styled(style).draw(&mut self.oled).unwrap();
+        line.into_styled(style).draw(&mut self.surface).map_err(display_err)
     }
 
     #[allow(dead_code)]
-    pub fn text(&mut self, x: i32, y: i32, txt: &str) {
+    pub fn text(&mut self, x: i32, y: i32, txt: &str) -> Result<(), WaveJumperError> {
         let draw_point = Point::new(x, y);
-        assert!(self.point_in_range(draw_point));
+        self.check_in_range(draw_point)?;
         
         let font = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
-        let text = Text::new(&txt, draw_point, font);
-        text.draw(&mut self.oled).unwrap();
+        let text = Text::new(txt, draw_point, font);
+        text.draw(&mut self.surface).map_err(display_err)?;
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn clear(&mut self) {
-        self.oled.clear(BinaryColor::Off).unwrap();
+    pub fn clear(&mut self) -> Result<(), WaveJumperError> {
+        self.surface.clear(BinaryColor::Off).map_err(display_err)
     }
 
     #[allow(dead_code)]
-    pub fn paint(&mut self) {
-        self.oled.flush().unwrap();
+    pub fn paint(&mut self) -> Result<(), WaveJumperError> {
+        self.surface.flush()
     }
 
     fn point_in_range(&self, pnt: Point) -> bool {
-        let oled_size: embedded_graphics::geometry::Size = self.oled.size();
+        let oled_size: Size = self.surface.size();
         let x_in_range: bool = (0..oled_size.width).contains(&(pnt.x as u32));
         let y_in_range: bool = (0..oled_size.height).contains(&(pnt.y as u32));
-        return x_in_range && y_in_range;
+        x_in_range && y_in_range
+    }
+
+    fn check_in_range(&self, pnt: Point) -> Result<(), WaveJumperError> {
+        if !self.point_in_range(pnt) {
+            return Err(WaveJumperError::Display(format!(
+                "({}, {}) is off the edge of the oled", pnt.x, pnt.y
+            )));
+        }
+        Ok(())
     }
     
 }
@@ -0,0 +1,210 @@
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::control::Command;
+use crate::error::WaveJumperError;
+use crate::fx::FxParam;
+use crate::settings::MAX_STEPS;
+
+// Everything we answer to starts with this
+const OSC_PREFIX: &str = "/wave_jumper/";
+// Biggest packet we'll take
+const OSC_MAX_PACKET: usize = 1536;
+// How long we leave the socket be after it fails on us
+const OSC_RECV_REST: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+}
+
+impl OscArg {
+    // Anything but a number we can play with is no number at all
+    fn num(self) -> Option<f32> {
+        match self {
+            OscArg::Int(i) => Some(i as f32),
+            OscArg::Float(f) => f.is_finite().then_some(f),
+            OscArg::Bool(_) => None,
+        }
+    }
+
+    fn switch(self) -> bool {
+        match self {
+            OscArg::Int(i) => i != 0,
+            OscArg::Float(f) => f >= 0.5,
+            OscArg::Bool(b) => b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+// Strings are nul terminated and padded out to four bytes
+fn read_str(buf: &[u8], at: &mut usize) -> Option<String> {
+    let len: usize = buf.get(*at..)?.iter().position(|&b| b == 0)?;
+    let s: String = String::from_utf8(buf[*at..*at + len].to_vec()).ok()?;
+    *at += (len + 4) & !3;
+    Some(s)
+}
+
+fn read_word(buf: &[u8], at: &mut usize) -> Option<[u8; 4]> {
+    let word: [u8; 4] = buf.get(*at..*at + 4)?.try_into().ok()?;
+    *at += 4;
+    Some(word)
+}
+
+fn parse_message(buf: &[u8]) -> Option<OscMessage> {
+    let mut at: usize = 0;
+    let address: String = read_str(buf, &mut at)?;
+    // Old senders leave the type tags off, there's nothing we can read then
+    let tags: String = read_str(buf, &mut at).unwrap_or_else(|| ",".into());
+    let mut args: Vec<OscArg> = Vec::new();
+    for tag in tags.strip_prefix(',')?.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(i32::from_be_bytes(read_word(buf, &mut at)?)),
+            'f' => OscArg::Float(f32::from_be_bytes(read_word(buf, &mut at)?)),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            _ => return None,
+        });
+    }
+    Some(OscMessage { address, args })
+}
+
+// Every message in a packet, opening up any bundles on the way
+pub fn parse(packet: &[u8]) -> Vec<OscMessage> {
+    let Some(mut rest) = packet.strip_prefix(b"#bundle\0") else {
+        return parse_message(packet).into_iter().collect();
+    };
+    // We play everything as it comes, so the time tag goes unread
+    rest = rest.get(8..).unwrap_or(&[]);
+    let mut messages: Vec<OscMessage> = Vec::new();
+    while let Some(len) = rest.get(..4).map(|w| u32::from_be_bytes(w.try_into().unwrap()) as usize) {
+        let Some(element) = rest.get(4..4 + len) else { break };
+        messages.extend(parse(element));
+        rest = &rest[4 + len..];
+    }
+    messages
+}
+
+// What a message asks of us, if it's for us at all
+pub fn command(msg: &OscMessage) -> Option<Command> {
+    let what: &str = msg.address.strip_prefix(OSC_PREFIX)?;
+    let arg = |k: usize| msg.args.get(k).copied();
+    match what {
+        "skip" => Some(Command::Skip),
+        "pause" => Some(Command::TogglePause),
+        "strategy" => Some(Command::NextStrategy),
+        "volume" => Some(Command::SetVolume(arg(0)?.num()?)),
+        "speed" => Some(Command::Speed(arg(0)?.num()?)),
+        "step_speed" => {
+            let step: f32 = arg(0)?.num().filter(|s| (0.0..MAX_STEPS as f32).contains(s))?;
+            Some(Command::StepSpeed(step as u32, arg(1)?.num()?))
+        }
+        "stretch" => Some(Command::Stretch(arg(0)?.switch())),
+        _ => {
+            let param: FxParam = FxParam::from_name(what.strip_prefix("fx/")?)?;
+            Some(Command::Fx(param, arg(0)?.num()?))
+        }
+    }
+}
+
+// Take orders over OSC on a thread of its own
+pub fn listen(port: u16, sender: Sender<Command>) -> Result<(), WaveJumperError> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))
+        .map_err(|e| WaveJumperError::Control(format!("can't listen for OSC on {}: {}", port, e)))?;
+    say!("@Listening for OSC on {}", port);
+    thread::spawn(move || {
+        let mut buf = [0u8; OSC_MAX_PACKET];
+        // Whether the last read failed too, so we only hear about it once
+        let mut failing: bool = false;
+        loop {
+            let len: usize = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    if !std::mem::replace(&mut failing, true) {
+                        warn!("@Warning -- OSC stopped reading: {}", e);
+                    }
+                    thread::sleep(OSC_RECV_REST);
+                    continue;
+                }
+            };
+            failing = false;
+            for cmd in parse(&buf[..len]).iter().filter_map(command) {
+                // Nobody left to listen
+                if sender.send(cmd).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a message the way any OSC sender would
+    fn message(address: &str, tags: &str, words: &[[u8; 4]]) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        for s in [address, tags] {
+            buf.extend(s.as_bytes());
+            buf.extend(std::iter::repeat_n(0, 4 - s.len() % 4));
+        }
+        buf.extend(words.iter().flatten());
+        buf
+    }
+
+    #[test]
+    fn reads_messages_and_bundles() {
+        let speed = message("/wave_jumper/step_speed", ",if", &[3i32.to_be_bytes(), 0.5f32.to_be_bytes()]);
+        let stretch = message("/wave_jumper/stretch", ",T", &[]);
+        assert_eq!(parse(&speed).iter().filter_map(command).collect::<Vec<_>>(), vec![Command::StepSpeed(3, 0.5)]);
+
+        let mut bundle: Vec<u8> = b"#bundle\0".to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        for msg in [&speed, &stretch] {
+            bundle.extend((msg.len() as u32).to_be_bytes());
+            bundle.extend(msg);
+        }
+        let commands: Vec<Command> = parse(&bundle).iter().filter_map(command).collect();
+        assert_eq!(commands, vec![Command::StepSpeed(3, 0.5), Command::Stretch(true)]);
+
+        let cutoff = message("/wave_jumper/fx/cutoff", ",f", &[0.25f32.to_be_bytes()]);
+        assert_eq!(parse(&cutoff).iter().filter_map(command).collect::<Vec<_>>(), vec![Command::Fx(FxParam::Cutoff, 0.25)]);
+
+        // Steps that aren't on the board aren't anything to us
+        for step in [-1, MAX_STEPS as i32] {
+            let speed = message("/wave_jumper/step_speed", ",if", &[step.to_be_bytes(), 0.5f32.to_be_bytes()]);
+            assert!(parse(&speed).iter().filter_map(command).next().is_none());
+        }
+
+        // The same volume twice is still that volume
+        let volume = message("/wave_jumper/volume", ",f", &[0.5f32.to_be_bytes()]);
+        assert_eq!(parse(&volume).iter().filter_map(command).collect::<Vec<_>>(), vec![Command::SetVolume(0.5)]);
+    }
+
+    #[test]
+    fn ignores_what_isnt_for_us() {
+        assert_eq!(parse(&message("/mixer/fader", ",f", &[1f32.to_be_bytes()])).iter().filter_map(command).count(), 0);
+        assert_eq!(parse(&message("/wave_jumper/speed", ",", &[])).iter().filter_map(command).count(), 0);
+        assert!(parse(&[0x2f, 0x77]).is_empty());
+    }
+
+    #[test]
+    fn ignores_numbers_that_arent() {
+        let speed = message("/wave_jumper/speed", ",f", &[f32::NAN.to_be_bytes()]);
+        let volume = message("/wave_jumper/volume", ",f", &[f32::INFINITY.to_be_bytes()]);
+        assert_eq!(parse(&speed).iter().filter_map(command).count(), 0);
+        assert_eq!(parse(&volume).iter().filter_map(command).count(), 0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering};
+
+// Which chip line each hole of the board is wired to, left to right.
+// Lines 0 to 7 are the first chip's channels and 8 to 15 the second's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMap {
+    pub demux: [u8; 16],
+    pub mux: [u8; 16],
+}
+
+impl Default for ChannelMap {
+    // The board in the schematic, its demux runs backwards
+    fn default() -> Self {
+        ChannelMap {
+            demux: std::array::from_fn(|k| 15 - k as u8),
+            mux: std::array::from_fn(|k| k as u8),
+        }
+    }
+}
+
+// A chip and its channel from a line number
+fn line_channel(line: u8) -> (usize, u32) {
+    ((line / 8) as usize, (line % 8) as u32)
+}
+
+impl ChannelMap {
+    // Straight through, for reading the chips' own line numbers
+    pub fn identity() -> Self {
+        ChannelMap { demux: std::array::from_fn(|k| k as u8), mux: std::array::from_fn(|k| k as u8) }
+    }
+
+    // Which demux chip and channel drives a step of the tape
+    pub fn demux_channel(&self, position: u32) -> (usize, u32) {
+        line_channel(self.demux[(position % 16) as usize])
+    }
+
+    // Which mux chip and channel a hole is read on
+    pub fn mux_channel(&self, hole: u32) -> (usize, u32) {
+        line_channel(self.mux[(hole % 16) as usize])
+    }
+
+    // Which step of the tape a demux chip and channel drives
+    pub fn demux_hole(&self, chip: usize, ch: u32) -> Option<u32> {
+        let line: u8 = (chip as u32 * 8 + ch) as u8;
+        self.demux.iter().position(|&l| l == line).map(|k| k as u32)
+    }
+
+    // Which step of the tape a mux chip and channel reads as
+    pub fn mux_hole(&self, chip: usize, ch: u32) -> Option<u32> {
+        let line: u8 = (chip as u32 * 8 + ch) as u8;
+        self.mux.iter().position(|&l| l == line).map(|k| k as u32)
+    }
+
+    // Every line used exactly once on both sides
+    pub fn is_valid(&self) -> bool {
+        let covers = |map: &[u8; 16]| (0..16).all(|line| map.contains(&line));
+        covers(&self.demux) && covers(&self.mux)
+    }
+}
+
+// The latest word read back from each step of the patch bay
+pub struct PatchMatrix {
+    pub(crate) rows: [AtomicU16; 16],
+    // Contacts that come and go
+    pub(crate) flaky: [AtomicU16; 16],
+    // How sure we are of each wire, in percent
+    pub(crate) confidence: [[AtomicU8; 16]; 16],
+    // The step the scanner keeps an eye on
+    pub(crate) position: AtomicU32,
+}
+
+impl PatchMatrix {
+    // A patch that stays put, for driving the engine without a board
+    pub fn from_rows(rows: [u16; 16]) -> Self {
+        PatchMatrix {
+            rows: rows.map(AtomicU16::new),
+            flaky: Default::default(),
+            confidence: std::array::from_fn(|_| std::array::from_fn(|_| AtomicU8::new(100))),
+            position: AtomicU32::new(0),
+        }
+    }
+
+    // Point the scanner at the step we are playing
+    pub fn follow(&self, position: u32) {
+        self.position.store(position % 16, Ordering::Relaxed);
+    }
+
+    pub fn row(&self, position: u32) -> u16 {
+        self.rows[(position % 16) as usize].load(Ordering::Relaxed)
+    }
+
+    // Every wire on the board, as the words out of each step
+    pub fn rows(&self) -> [u16; 16] {
+        std::array::from_fn(|k| self.rows[k].load(Ordering::Relaxed))
+    }
+
+    // Every intermittent contact, laid out like `rows`
+    pub fn flaky(&self) -> [u16; 16] {
+        std::array::from_fn(|k| self.flaky[k].load(Ordering::Relaxed))
+    }
+
+    pub fn confidence(&self) -> [[u8; 16]; 16] {
+        std::array::from_fn(|r| std::array::from_fn(|c| self.confidence[r][c].load(Ordering::Relaxed)))
+    }
+}
@@ -1,21 +1,161 @@
+use crate::error::WaveJumperError;
+
 #[allow(unused_imports)]
 use rpi_pal::gpio::{InputPin, IoPin, OutputPin};
-use rpi_pal::gpio::{Gpio, Mode};
+use rpi_pal::gpio::{Bias, Gpio, Level, Mode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 #[allow(dead_code)]
-pub fn get_digital_out(pin: u8) -> OutputPin {
-    let io = Gpio::new().expect("GPIOs not accessible.");
-    io.get(pin).unwrap().into_output()
+pub fn get_digital_out(pin: u8) -> Result<OutputPin, WaveJumperError> {
+    let io = Gpio::new()?;
+    Ok(io.get(pin)?.into_output())
 }
 
 #[allow(dead_code)]
-pub fn get_digital_in(pin: u8) -> InputPin {
-    let io = Gpio::new().expect("GPIOs not accessible.");
-    io.get(pin).unwrap().into_input()
+pub fn get_digital_in(pin: u8) -> Result<InputPin, WaveJumperError> {
+    let io = Gpio::new()?;
+    Ok(io.get(pin)?.into_input())
 }
 
 #[allow(dead_code)]
-pub fn get_digital_generic(pin: u8, mode: Mode) -> IoPin {
-    let io = Gpio::new().expect("GPIOs not accessible.");
-    io.get(pin).unwrap().into_io(mode)
+pub fn get_digital_generic(pin: u8, mode: Mode) -> Result<IoPin, WaveJumperError> {
+    let io = Gpio::new()?;
+    Ok(io.get(pin)?.into_io(mode))
+}
+
+// A line we can drive
+pub trait DigitalOut: Send {
+    fn write(&mut self, lv: Level);
+
+    fn set_high(&mut self) {
+        self.write(Level::High);
+    }
+
+    fn set_low(&mut self) {
+        self.write(Level::Low);
+    }
+}
+
+// A line we can read
+pub trait DigitalIn: Send {
+    fn read(&self) -> Level;
+
+    fn is_high(&self) -> bool {
+        self.read() == Level::High
+    }
+
+    fn is_low(&self) -> bool {
+        self.read() == Level::Low
+    }
+}
+
+impl DigitalOut for OutputPin {
+    fn write(&mut self, lv: Level) {
+        OutputPin::write(self, lv);
+    }
+}
+
+impl DigitalIn for InputPin {
+    fn read(&self) -> Level {
+        InputPin::read(self)
+    }
+}
+
+impl DigitalIn for IoPin {
+    fn read(&self) -> Level {
+        IoPin::read(self)
+    }
+}
+
+// Somewhere to get pins from, real or otherwise
+pub trait GpioBackend: Send + Sync {
+    fn output(&self, pin: u8) -> Result<Box<dyn DigitalOut>, WaveJumperError>;
+    fn input(&self, pin: u8, bias: Bias) -> Result<Box<dyn DigitalIn>, WaveJumperError>;
+}
+
+// The Raspberry Pi's own header
+pub struct RpiGpio;
+
+impl GpioBackend for RpiGpio {
+    fn output(&self, pin: u8) -> Result<Box<dyn DigitalOut>, WaveJumperError> {
+        Ok(Box::new(get_digital_out(pin)?))
+    }
+
+    fn input(&self, pin: u8, bias: Bias) -> Result<Box<dyn DigitalIn>, WaveJumperError> {
+        let mut io: IoPin = get_digital_generic(pin, Mode::Input)?;
+        io.set_bias(bias);
+        Ok(Box::new(io))
+    }
+}
+
+// Computes the level of an input from the levels of every driven pin
+pub type Resolver = dyn Fn(&HashMap<u8, Level>, u8) -> Option<Level> + Send + Sync;
+
+// Pins that live in memory, for simulators and tests.
+// Inputs read whatever was last set on them, unless a resolver has something to say.
+#[derive(Clone, Default)]
+pub struct MemGpio {
+    levels: Arc<Mutex<HashMap<u8, Level>>>,
+    biases: Arc<Mutex<HashMap<u8, Bias>>>,
+    resolver: Option<Arc<Resolver>>,
+}
+
+impl MemGpio {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        MemGpio::default()
+    }
+
+    #[allow(dead_code)]
+    pub fn with_resolver(resolver: Arc<Resolver>) -> Self {
+        MemGpio { resolver: Some(resolver), ..MemGpio::default() }
+    }
+
+    // Force a level onto a pin, as if from the outside world
+    #[allow(dead_code)]
+    pub fn set(&self, pin: u8, lv: Level) {
+        self.levels.lock().unwrap().insert(pin, lv);
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, pin: u8) -> Level {
+        MemPin { pin, gpio: self.clone() }.read()
+    }
+}
+
+struct MemPin {
+    pin: u8,
+    gpio: MemGpio,
+}
+
+impl DigitalOut for MemPin {
+    fn write(&mut self, lv: Level) {
+        self.gpio.set(self.pin, lv);
+    }
+}
+
+impl DigitalIn for MemPin {
+    fn read(&self) -> Level {
+        let levels = self.gpio.levels.lock().unwrap();
+        let resolved: Option<Level> = self.gpio.resolver.as_ref().and_then(|r| r(&levels, self.pin));
+        let pulled: Level = match self.gpio.biases.lock().unwrap().get(&self.pin) {
+            Some(Bias::PullUp) => Level::High,
+            _ => Level::Low,
+        };
+        resolved.or_else(|| levels.get(&self.pin).copied()).unwrap_or(pulled)
+    }
+}
+
+impl GpioBackend for MemGpio {
+    fn output(&self, pin: u8) -> Result<Box<dyn DigitalOut>, WaveJumperError> {
+        self.set(pin, Level::Low);
+        Ok(Box::new(MemPin { pin, gpio: self.clone() }))
+    }
+
+    fn input(&self, pin: u8, bias: Bias) -> Result<Box<dyn DigitalIn>, WaveJumperError> {
+        self.biases.lock().unwrap().insert(pin, bias);
+        Ok(Box::new(MemPin { pin, gpio: self.clone() }))
+    }
 }
@@ -0,0 +1,229 @@
+use rodio::source::SeekError;
+use rodio::Source;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Frames between the router looking for where the step wants to be
+const ROUTE_POLL_FRAMES: usize = 64;
+// How long the gains take to get most of the way over, so a pan or a hop between speakers never clicks
+const ROUTE_GLIDE_MS: f32 = 5.0;
+// Most outputs we'll ask an interface for
+pub const MAX_OUTPUTS: u16 = 32;
+
+// Where a step sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    // -1 hard left, 1 hard right
+    pub pan: f32,
+    // 0 folds the tape to mono, 1 leaves it be, 2 spreads it as wide as it goes
+    pub width: f32,
+    // The output the left lands on, the right going to the one after.
+    // Past the last output it wraps round to the first.
+    pub output: u16,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement { pan: 0.0, width: 1.0, output: 0 }
+    }
+}
+
+impl Placement {
+    // How much of the tape's left and right goes to each output
+    fn gains(self, outputs: usize) -> Vec<[f32; 2]> {
+        // A balance rather than a pan law, so nothing gets louder than the tape
+        let pan: f32 = self.pan.clamp(-1.0, 1.0);
+        let (left, right) = ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0));
+        let mut gains: Vec<[f32; 2]> = vec![[0.0; 2]; outputs.max(1)];
+        if outputs <= 1 {
+            gains[0] = [left / 2.0, right / 2.0];
+            return gains;
+        }
+        let at: usize = self.output as usize % outputs;
+        gains[at][0] = left;
+        gains[(at + 1) % outputs][1] = right;
+        gains
+    }
+}
+
+// The instrument's hand on where the deck sounds, shared with the audio thread
+#[derive(Debug)]
+pub struct Routing {
+    placement: Mutex<Placement>,
+    outputs: u16,
+}
+
+impl Routing {
+    pub fn new(outputs: u16) -> Arc<Self> {
+        Arc::new(Routing { placement: Mutex::new(Placement::default()), outputs: outputs.clamp(1, MAX_OUTPUTS) })
+    }
+
+    pub fn set(&self, placement: Placement) {
+        *self.placement.lock().unwrap_or_else(|e| e.into_inner()) = placement;
+    }
+
+    pub fn placement(&self) -> Placement {
+        *self.placement.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn outputs(&self) -> u16 {
+        self.outputs
+    }
+
+    // Spread a source over every output, however many channels it came with
+    pub fn route<S: Source>(self: &Arc<Self>, source: S) -> Router<S> {
+        let outputs: usize = self.outputs as usize;
+        let placement: Placement = self.placement();
+        let gains: Vec<[f32; 2]> = placement.gains(outputs);
+        let glide: f32 = 1.0 - (-1000.0 / (ROUTE_GLIDE_MS * source.sample_rate().max(1) as f32)).exp();
+        Router {
+            inner: source,
+            routing: Arc::clone(self),
+            placement,
+            target: gains.clone(),
+            gains,
+            glide,
+            until_poll: ROUTE_POLL_FRAMES,
+            frame: [0.0; 2],
+            channel: 0,
+        }
+    }
+}
+
+// The tape's left and right, panned, widened and sent to the step's outputs
+pub struct Router<S> {
+    inner: S,
+    routing: Arc<Routing>,
+    placement: Placement,
+    // Where the gains are headed, and where they've got to
+    target: Vec<[f32; 2]>,
+    gains: Vec<[f32; 2]>,
+    glide: f32,
+    until_poll: usize,
+    // The frame we're sending out, and the output we're up to
+    frame: [f32; 2],
+    channel: usize,
+}
+
+impl<S: Source> Router<S> {
+    // Once a frame, before the first output
+    fn on_frame(&mut self) -> Option<()> {
+        // A mono tape is the same on both sides, and past the first two channels goes unheard
+        let channels: u16 = self.inner.channels().max(1);
+        let left: f32 = self.inner.next()?;
+        let right: f32 = if channels > 1 { self.inner.next()? } else { left };
+        for _ in 2..channels {
+            self.inner.next()?;
+        }
+
+        self.until_poll -= 1;
+        if self.until_poll == 0 {
+            self.until_poll = ROUTE_POLL_FRAMES;
+            let moved: Option<Placement> = self.routing.placement.try_lock().ok().map(|p| *p).filter(|p| *p != self.placement);
+            if let Some(placement) = moved {
+                self.placement = placement;
+                self.target = placement.gains(self.gains.len());
+            }
+        }
+        for (gain, target) in self.gains.iter_mut().zip(&self.target) {
+            gain[0] += (target[0] - gain[0]) * self.glide;
+            gain[1] += (target[1] - gain[1]) * self.glide;
+        }
+
+        // Mid and side, with the side turned up or down
+        let mid: f32 = (left + right) / 2.0;
+        let side: f32 = (left - right) / 2.0 * self.placement.width.clamp(0.0, 2.0);
+        self.frame = [mid + side, mid - side];
+        Some(())
+    }
+}
+
+impl<S: Source> Iterator for Router<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.on_frame()?;
+        }
+        let [left, right] = self.gains[self.channel];
+        self.channel = (self.channel + 1) % self.gains.len();
+        Some(left * self.frame[0] + right * self.frame[1])
+    }
+}
+
+impl<S: Source> Source for Router<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.gains.len() as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn tape(channels: u16, samples: Vec<f32>) -> SamplesBuffer {
+        SamplesBuffer::new(channels, 1000, samples)
+    }
+
+    #[test]
+    fn pans_and_widens() {
+        let routing = Routing::new(2);
+        routing.set(Placement { pan: -1.0, ..Placement::default() });
+        let played: Vec<f32> = routing.route(tape(1, vec![0.5; 4])).collect();
+        assert_eq!(played, [0.5, 0.0, 0.5, 0.0, 0.5, 0.0, 0.5, 0.0]);
+
+        routing.set(Placement { width: 0.0, ..Placement::default() });
+        let played: Vec<f32> = routing.route(tape(2, vec![1.0, 0.0])).collect();
+        assert_eq!(played, [0.5, 0.5]);
+        routing.set(Placement { width: 2.0, ..Placement::default() });
+        let played: Vec<f32> = routing.route(tape(2, vec![1.0, 0.5])).collect();
+        assert_eq!(played, [1.25, 0.25]);
+    }
+
+    #[test]
+    fn sends_steps_to_their_outputs() {
+        let routing = Routing::new(4);
+        routing.set(Placement { output: 3, ..Placement::default() });
+        let played: Vec<f32> = routing.route(tape(2, vec![1.0, 0.5, 0.25])).collect();
+        // The right wraps round to the first output, and half a frame is no frame at all
+        assert_eq!(played, [0.5, 0.0, 0.0, 1.0]);
+
+        // Moving mid-tape glides over rather than jumping
+        routing.set(Placement::default());
+        let router = routing.route(tape(1, vec![1.0; 200]));
+        routing.set(Placement { output: 2, ..Placement::default() });
+        let played: Vec<f32> = router.collect();
+        let first: Vec<f32> = played.chunks(4).map(|frame| frame[0]).collect();
+        let moved: usize = ROUTE_POLL_FRAMES - 1;
+        assert!(first[..moved].iter().all(|&g| g == 1.0));
+        assert!(first[moved] < 1.0 && first[moved] > 0.5);
+        assert!(first[199] < 0.01);
+    }
+
+    #[test]
+    fn outputs_we_dont_have_wrap_round() {
+        // Routed to output 5 of an interface with 2, so 5 lands on 1 and its right on 0
+        let routing = Routing::new(2);
+        routing.set(Placement { output: 5, ..Placement::default() });
+        let played: Vec<f32> = routing.route(tape(2, vec![1.0, 0.5])).collect();
+        assert_eq!(played, [0.5, 1.0]);
+    }
+}
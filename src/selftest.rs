@@ -0,0 +1,378 @@
+use rpi_pal::gpio::Bias;
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::control::Command;
+use crate::error::WaveJumperError;
+use crate::input::{InputEvent, Inputs};
+use crate::mux::PatchBay;
+use crate::oled::{Display, Surface};
+use crate::patch::ChannelMap;
+use crate::pinio::GpioBackend;
+use crate::uifb::*;
+
+// Time to get every wire off the board before we look
+const PULL_WAIT: Duration = Duration::from_secs(3);
+// How long we give each hole of the walk before calling it dead, a press skips it sooner
+const WALK_TIMEOUT: Duration = Duration::from_secs(20);
+const WALK_POLL: Duration = Duration::from_millis(20);
+// Polls in a row a wire has to read the same before we take it
+const WALK_STEADY: u32 = 5;
+// Nobody's walking a wire if this many holes in a row turn up nothing
+const WALK_GIVE_UP: usize = 2;
+const test_ui_xcoord: i32 = 2;
+const test_ui_ystart: i32 = 30;
+const test_ui_ypad: i32 = 10;
+
+// Select lines that look swapped, as pairs of line numbers
+type Crossings = Vec<(u32, u32)>;
+
+// What one hole of the walk read back as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    // Nothing turned up, or we were told to skip it
+    Skipped,
+    // Read back as another hole, the select lines are crossed
+    Crossed(u32, u32),
+    // Both demux chips answered, an enable is stuck on
+    BothChips,
+    // Several mux channels answered, they're shorted together
+    Shorted(Vec<u32>),
+}
+
+// Everything the self test found out about the board
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    // Mux channels that read high with nothing driving them
+    pub stuck_high: Vec<u32>,
+    // Mux channels that read low even with the inputs pulled up
+    pub stuck_low: Vec<u32>,
+    // Step to channel connections on a board that should be empty
+    pub shorts: Vec<(u32, u32)>,
+    // A wire from a hole to the same mux hole for each select line, and everywhere it showed up
+    pub walk: Vec<(u32, Vec<(u32, u32)>)>,
+    // How the holes are meant to be wired
+    pub map: ChannelMap,
+}
+
+// Every (step, channel) connection set in `rows`
+fn connections(rows: &[u16; 16]) -> Vec<(u32, u32)> {
+    (0..16u32).flat_map(|p| (0..16u32).filter(move |&c| rows[p as usize] >> c & 1 == 1).map(move |c| (p, c))).collect()
+}
+
+// The select line a channel with a single bit set sits on
+fn select_line(channel: u32) -> Option<u32> {
+    channel.is_power_of_two().then(|| channel.trailing_zeros())
+}
+
+// A hole on each select line of either side. Crossing a pair of lines swaps two of these over,
+// anything else wrong with the board shows up with it empty.
+fn select_holes(map: &ChannelMap) -> Vec<u32> {
+    let mut holes: Vec<u32> = (0..3).flat_map(|b| [
+        (0..16).find(|&h| map.demux_channel(h).1 == 1 << b),
+        (0..16).find(|&h| map.mux_channel(h).1 == 1 << b),
+    ]).flatten().collect();
+    holes.sort();
+    holes.dedup();
+    holes
+}
+
+impl Report {
+    // Judge an empty board from its idle mux word pulled down then up, and a scan of every step
+    pub fn from_empty(idle: u16, pulled_up: u16, rows: &[u16; 16]) -> Self {
+        let stuck_high: Vec<u32> = (0..16).filter(|&c| idle >> c & 1 == 1).collect();
+        let stuck_low: Vec<u32> = (0..16).filter(|&c| pulled_up >> c & 1 == 0).collect();
+        let unstuck: [u16; 16] = rows.map(|w| w & !idle);
+        Report { stuck_high, stuck_low, shorts: connections(&unstuck), ..Report::default() }
+    }
+
+    pub fn verdict(step: u32, hits: &[(u32, u32)]) -> Verdict {
+        match hits {
+            [] => Verdict::Skipped,
+            [(p, c)] if (*p, *c) == (step, step) => Verdict::Ok,
+            [(p, c)] => Verdict::Crossed(*p, *c),
+            _ if hits.iter().all(|&(_, c)| c == hits[0].1) => Verdict::BothChips,
+            _ => Verdict::Shorted(hits.iter().map(|&(_, c)| c).collect()),
+        }
+    }
+
+    // Demux then mux, judged from holes that only use one select line each
+    pub fn crossed_selects(&self) -> (Crossings, Crossings) {
+        let (mut demux, mut mux): (Crossings, Crossings) = (Vec::new(), Vec::new());
+        for (step, hits) in self.walk.iter() {
+            let Verdict::Crossed(p, c) = Report::verdict(*step, hits) else { continue };
+            let pairs = [
+                (&mut demux, self.map.demux_channel(*step).1, self.map.demux_channel(p).1),
+                (&mut mux, self.map.mux_channel(*step).1, self.map.mux_channel(c).1),
+            ];
+            for (found, want, got) in pairs {
+                if let (Some(a), Some(b)) = (select_line(want), select_line(got)) {
+                    let pair: (u32, u32) = (a.min(b), a.max(b));
+                    if a != b && !found.contains(&pair) {
+                        found.push(pair);
+                    }
+                }
+            }
+        }
+        (demux, mux)
+    }
+
+    // The whole story, for the terminal
+    pub fn table(&self) -> Vec<String> {
+        let list = |items: Vec<String>| if items.is_empty() { "none".to_string() } else { items.join(" ") };
+        let mut lines: Vec<String> = vec![
+            format!("{:<12}{}", "stuck high", list(self.stuck_high.iter().map(|c| format!("{:02}", c)).collect())),
+            format!("{:<12}{}", "stuck low", list(self.stuck_low.iter().map(|c| format!("{:02}", c)).collect())),
+            format!("{:<12}{}", "shorts", list(self.shorts.iter().map(|(p, c)| format!("x{:02}>{:02}", p, c)).collect())),
+        ];
+        let (demux, mux) = self.crossed_selects();
+        let crossed: Vec<String> = [("demux", demux), ("mux", mux)].into_iter()
+            .flat_map(|(side, found)| found.into_iter().map(move |(a, b)| format!("{} s{}/s{}", side, a, b)))
+            .collect();
+        lines.push(format!("{:<12}{}", "crossed", list(crossed)));
+        if self.walk.is_empty() {
+            lines.push("no wire was walked, so crossed lines went unchecked".into());
+            return lines;
+        }
+        lines.push(format!("{:<6}{:<16}{}", "hole", "read", "verdict"));
+        for (step, hits) in self.walk.iter() {
+            let read: String = list(hits.iter().map(|(p, c)| format!("x{:02}>{:02}", p, c)).collect());
+            let verdict: String = match Report::verdict(*step, hits) {
+                Verdict::Ok => "ok".into(),
+                Verdict::Skipped => "skipped".into(),
+                Verdict::Crossed(..) => "crossed select".into(),
+                Verdict::BothChips => "demux enable stuck on".into(),
+                Verdict::Shorted(_) => "shorted channels".into(),
+            };
+            lines.push(format!("{:<6}{:<16}{}", format!("x{:02}", step), read, verdict));
+        }
+        lines
+    }
+
+    // A few lines that fit on the oled
+    pub fn summary(&self) -> Vec<String> {
+        let count = |v: Verdict| self.walk.iter().filter(|(s, h)| Report::verdict(*s, h) == v).count();
+        let odd: usize = self.walk.len() - count(Verdict::Ok) - count(Verdict::Skipped);
+        let (demux, mux) = self.crossed_selects();
+        vec![
+            format!("stuck hi {} lo {}", self.stuck_high.len(), self.stuck_low.len()),
+            format!("shorts     {}", self.shorts.len()),
+            format!("skipped {} odd {}", count(Verdict::Skipped), odd),
+            format!("crossed    {}", demux.len() + mux.len()),
+        ]
+    }
+}
+
+fn scan_all(bay: &mut PatchBay) -> Result<[u16; 16], WaveJumperError> {
+    let mut rows = [0u16; 16];
+    for (p, row) in rows.iter_mut().enumerate() {
+        *row = bay.scan(p as u32)?;
+    }
+    Ok(rows)
+}
+
+// Drive every select code on each demux chip in turn, with nothing wired in, then read the idle board
+// pulled down and pulled up. A line a hole doesn't use never reads as anything.
+pub fn check_empty(bay: &mut PatchBay, gpio: &dyn GpioBackend, map: &ChannelMap) -> Result<Report, WaveJumperError> {
+    let mut rows = [0u16; 16];
+    for chip in 0..2 {
+        for ch in 0..8 {
+            let word: u16 = bay.drive(chip, ch)?;
+            if let Some(step) = map.demux_hole(chip, ch) {
+                rows[step as usize] = word;
+            }
+        }
+    }
+    let idle: u16 = bay.scan_idle();
+    bay.pull(gpio, Bias::PullUp)?;
+    let pulled_up: u16 = bay.scan_idle();
+    bay.pull(gpio, Bias::PullDown)?;
+    Ok(Report { map: *map, ..Report::from_empty(idle, pulled_up, &rows) })
+}
+
+// Watch the panel's buttons for a press to skip a hole
+fn listen(gpio: &dyn GpioBackend, config: &Config) -> Receiver<Command> {
+    let (sender, presses) = mpsc::channel();
+    Inputs::new(gpio, config).spawn(config.input_poll, sender);
+    presses
+}
+
+fn show_lines<S: Surface>(oled: &mut Display<S>, title: &str, lines: &[String]) -> Result<(), WaveJumperError> {
+    oled.clear()?;
+    show_title(oled, title)?;
+    for (k, line) in lines.iter().enumerate() {
+        oled.text(test_ui_xcoord, test_ui_ystart - test_ui_ypad + k as i32 * test_ui_ypad, line)?;
+    }
+    oled.paint()
+}
+
+// Ask for a wire from `step` to the same mux hole and wait for it to turn up.
+// None if we were told to skip it, nothing at all if it never turned up.
+fn walk_hole<S: Surface>(bay: &mut PatchBay, oled: &mut Display<S>, presses: &Receiver<Command>, what: &str, step: u32, baseline: &[u16; 16], last: &[(u32, u32)]) -> Result<Option<Vec<(u32, u32)>>, WaveJumperError> {
+    oled.clear()?;
+    show_title(oled, &format!("{} x{:02}", what, step))?;
+    let mut demux_dots: Vec<Dot> = get_dot_row(demux_ui_ycoord, dot_ui_size, dot_ui_xpad, 16);
+    let mut muxin_dots: Vec<Dot> = get_dot_row(muxin_ui_ycoord, dot_ui_size, dot_ui_xpad, 16);
+    draw_dot_row(oled, &mut demux_dots)?;
+    draw_dot_row(oled, &mut muxin_dots)?;
+    fill_dot(oled, &mut demux_dots[step as usize])?;
+    fill_dot(oled, &mut muxin_dots[step as usize])?;
+    oled.text(test_ui_xcoord, test_ui_ystart + test_ui_ypad, &format!("wire x{:02} to {:02}", step, step))?;
+    oled.paint()?;
+    say!("@{} -- wire x{:02} to {:02}, or press to skip it", what, step, step);
+
+    let deadline: Instant = Instant::now() + WALK_TIMEOUT;
+    let (mut seen, mut steady): (Vec<(u32, u32)>, u32) = (Vec::new(), 0);
+    while Instant::now() < deadline {
+        if presses.try_iter().any(|cmd| matches!(cmd, Command::Input(InputEvent::Press(..)))) {
+            say!("@{} -- skipped x{:02}", what, step);
+            return Ok(None);
+        }
+        let rows: [u16; 16] = scan_all(bay)?;
+        // The wire from the last hole doesn't count if it's still in
+        let hits: Vec<(u32, u32)> = connections(&std::array::from_fn(|p| rows[p] & !baseline[p]))
+            .into_iter()
+            .filter(|hit| !last.contains(hit))
+            .collect();
+        steady = if hits == seen { steady + 1 } else { 0 };
+        seen = hits;
+        if steady >= WALK_STEADY && !seen.is_empty() {
+            return Ok(Some(seen));
+        }
+        thread::sleep(WALK_POLL);
+    }
+    Ok(Some(Vec::new()))
+}
+
+// Check the empty board, then walk a wire through a hole on each select line to look for crossed ones
+pub fn run<S: Surface>(gpio: &dyn GpioBackend, oled: &mut Display<S>, config: &Config) -> Result<Report, WaveJumperError> {
+    let mut bay = PatchBay::new(gpio, config)?;
+    let presses: Receiver<Command> = listen(gpio, config);
+
+    say!("@Self test -- pull every wire");
+    show_lines(oled, "self test", &["pull every wire".into()])?;
+    thread::sleep(PULL_WAIT);
+    let mut report: Report = check_empty(&mut bay, gpio, &config.channel_map)?;
+
+    // Anything already lit on the empty board is in the report, leave it out of the walk
+    let idle: u16 = bay.scan_idle();
+    let baseline: [u16; 16] = scan_all(&mut bay)?.map(|w| w | idle);
+    let mut last: Vec<(u32, u32)> = Vec::new();
+    // Holes that never turned up, skipping one means somebody's there
+    let mut timed_out: usize = 0;
+    for step in select_holes(&config.channel_map) {
+        let walked: Option<Vec<(u32, u32)>> = walk_hole(&mut bay, oled, &presses, "Self test", step, &baseline, &last)?;
+        timed_out += walked.as_ref().is_some_and(|h| h.is_empty()) as usize;
+        let hits: Vec<(u32, u32)> = walked.unwrap_or_default();
+        report.walk.push((step, hits.clone()));
+        if timed_out == WALK_GIVE_UP && report.walk.len() == WALK_GIVE_UP {
+            say!("@Self test -- nobody is walking a wire, skipping the rest");
+            report.walk.clear();
+            break;
+        }
+        last = hits;
+    }
+
+    for line in report.table() {
+        say!("{}", line);
+    }
+    show_lines(oled, "self test", &report.summary())?;
+    Ok(report)
+}
+
+// Walk a wire down the board reading the chips' own lines, to learn how each hole is wired
+pub fn calibrate<S: Surface>(gpio: &dyn GpioBackend, oled: &mut Display<S>, config: &Config) -> Result<ChannelMap, WaveJumperError> {
+    let mut bay = PatchBay::new(gpio, &Config { channel_map: ChannelMap::identity(), ..config.clone() })?;
+
+    say!("@Calibration -- pull every wire");
+    show_lines(oled, "calibrate", &["pull every wire".into()])?;
+    thread::sleep(PULL_WAIT);
+    let idle: u16 = bay.scan_idle();
+    let baseline: [u16; 16] = scan_all(&mut bay)?.map(|w| w | idle);
+
+    let presses: Receiver<Command> = listen(gpio, config);
+    let mut map = ChannelMap::identity();
+    let mut last: Vec<(u32, u32)> = Vec::new();
+    for hole in 0..16 {
+        let hits: Option<Vec<(u32, u32)>> = walk_hole(&mut bay, oled, &presses, "Calibration", hole, &baseline, &last)?;
+        let Some(&[(demux_line, mux_line)]) = hits.as_deref() else {
+            let why: &str = match hits {
+                None => "was skipped",
+                Some(h) if h.is_empty() => "never answered",
+                Some(_) => "answered in more than one place",
+            };
+            show_lines(oled, "calibrate", &[format!("x{:02} {}", hole, why), "try --selftest".into()])?;
+            return Err(WaveJumperError::Patch(format!("x{:02} {}, try --selftest", hole, why)));
+        };
+        map.demux[hole as usize] = demux_line as u8;
+        map.mux[hole as usize] = mux_line as u8;
+        last = vec![(demux_line, mux_line)];
+    }
+    if !map.is_valid() {
+        show_lines(oled, "calibrate", &["holes share a line".into(), "try --selftest".into()])?;
+        return Err(WaveJumperError::Patch("two holes answered on the same line, try --selftest".into()));
+    }
+    show_lines(oled, "calibrate", &["all 16 holes found".into()])?;
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pinio::MemGpio;
+    use crate::sim::Breadboard;
+
+    #[test]
+    fn empty_board_finds_stuck_and_shorted() {
+        let mut rows = [1u16 << 3; 16];
+        rows[5] |= 1 << 9;
+        let report = Report::from_empty(1 << 3, !(1 << 7), &rows);
+        assert_eq!(report.stuck_high, vec![3]);
+        assert_eq!(report.stuck_low, vec![7]);
+        assert_eq!(report.shorts, vec![(5, 9)]);
+    }
+
+    #[test]
+    fn empty_check_drives_every_channel() {
+        let board = Breadboard::new();
+        let config = Config { mux_settle: Duration::ZERO, ..Config::default() };
+        let gpio: MemGpio = board.gpio(&config);
+        let mut bay = PatchBay::new(&gpio, &config).unwrap();
+        // A stray bit of wire where the board should be empty
+        board.toggle_wire(11, 4);
+        let report: Report = check_empty(&mut bay, &gpio, &config.channel_map).unwrap();
+        assert_eq!(report.shorts, vec![(11, 4)]);
+        assert!(report.stuck_high.is_empty() && report.stuck_low.is_empty());
+    }
+
+    #[test]
+    fn walk_only_visits_single_select_lines() {
+        assert_eq!(select_holes(&ChannelMap::identity()), vec![1, 2, 4]);
+        // The schematic's demux runs backwards, so its lines come up on other holes
+        assert_eq!(select_holes(&ChannelMap::default()), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn walk_tells_crossed_lines_from_dead_ones() {
+        // Mux select lines 0 and 1 swapped, hole 4 dead and hole 8's demux enable stuck
+        let report = Report {
+            walk: vec![
+                (0, vec![(0, 0)]),
+                (1, vec![(1, 2)]),
+                (2, vec![(2, 1)]),
+                (4, vec![]),
+                (8, vec![(8, 8), (0, 8)]),
+            ],
+            ..Report::default()
+        };
+        assert_eq!(Report::verdict(0, &report.walk[0].1), Verdict::Ok);
+        assert_eq!(Report::verdict(4, &report.walk[3].1), Verdict::Skipped);
+        assert_eq!(Report::verdict(8, &report.walk[4].1), Verdict::BothChips);
+        assert_eq!(report.crossed_selects(), (vec![], vec![(0, 1)]));
+        assert!(report.summary().iter().all(|line| line.len() <= 21));
+    }
+}
@@ -0,0 +1,390 @@
+use crate::deck::Cloud;
+use crate::fx::FxParams;
+use crate::jump::{bit_at, JumpStrategy};
+use crate::modulate::ModRoute;
+use crate::tape::bank_name;
+
+// The hardware only has so many holes
+pub const MAX_STEPS: u32 = 16;
+const MIN_STEPS: u32 = 2;
+const TEMPO_RANGE: (u32, u32) = (60, 240);
+const TEMPO_STEP: u32 = 5;
+pub const VOLUME_MAX: f32 = 2.0;
+const VOLUME_STEP: f32 = 0.1;
+const CROSSFADES_MS: [u32; 7] = [0, 2, 5, 10, 20, 50, 100];
+// Most times a step can go back to its start
+pub const MAX_RATCHETS: u32 = 8;
+// Octaves, fifths and fourths either side of the tape's own speed
+const SPEEDS: [f32; 7] = [0.5, 2.0 / 3.0, 0.75, 1.0, 4.0 / 3.0, 1.5, 2.0];
+// Slowest and fastest a step can play, whatever speeds it's given
+pub const SPEED_RANGE: (f32, f32) = (0.25, 4.0);
+
+// Which steps play backwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reverse {
+    // Everything forwards, marked or not
+    Off,
+    // Only the steps marked to
+    #[default]
+    Marked,
+    // Those, and any step we jumped back to
+    Back,
+    // The whole tape backwards, a step at a time
+    All,
+}
+
+impl Reverse {
+    pub const ALL: [Reverse; 4] = [Reverse::Off, Reverse::Marked, Reverse::Back, Reverse::All];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Reverse::Off => "off",
+            Reverse::Marked => "marked",
+            Reverse::Back => "back",
+            Reverse::All => "all",
+        }
+    }
+}
+
+// Everything a performer can change without a rebuild
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub strategy: JumpStrategy,
+    // Beats per minute, or None to fit the steps to the tape
+    pub tempo: Option<u32>,
+    pub steps: u32,
+    pub volume: f32,
+    pub crossfade_ms: u32,
+    pub bank: usize,
+    pub reverse: Reverse,
+    // One bit for each step marked to play backwards
+    pub reversed_steps: u16,
+    // How fast the tape runs, 1 is as recorded
+    pub speed: f32,
+    // Keep the tape's pitch when the speed changes
+    pub stretch: bool,
+    // Each step's speed, on top of the one above
+    pub step_speeds: [f32; 16],
+    // Play each step as a cloud of grains rather than straight
+    pub granular: bool,
+    pub cloud: Cloud,
+    // How many times each step starts over within itself
+    pub ratchets: [u32; 16],
+    // Or how much of its start it repeats, 0 for none
+    pub stutters_ms: [u32; 16],
+    // The chance in percent that either happens each time round
+    pub repeat_chance: [u32; 16],
+    // A wire from a step into this hole ratchets it, rather than jumping there
+    pub ratchet_hole: Option<u32>,
+    pub hole_ratchets: u32,
+    // The effects after the deck, and what the patch does to them
+    pub fx: FxParams,
+    pub mods: Vec<ModRoute>,
+    // Where each step sits from -1 left to 1 right, and how wide the tape is, 1 as recorded
+    pub step_pans: [f32; 16],
+    pub width: f32,
+    // How many outputs the interface has, and the first one each step plays out of
+    pub outputs: u16,
+    pub step_outputs: [u16; 16],
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            strategy: JumpStrategy::default(),
+            tempo: None,
+            steps: MAX_STEPS,
+            volume: 1.0,
+            crossfade_ms: 0,
+            bank: 0,
+            reverse: Reverse::default(),
+            reversed_steps: 0,
+            speed: 1.0,
+            stretch: false,
+            step_speeds: [1.0; 16],
+            granular: false,
+            cloud: Cloud::default(),
+            ratchets: [1; 16],
+            stutters_ms: [0; 16],
+            repeat_chance: [100; 16],
+            ratchet_hole: None,
+            hole_ratchets: 2,
+            fx: FxParams::default(),
+            mods: Vec::new(),
+            step_pans: [0.0; 16],
+            width: 1.0,
+            outputs: 2,
+            step_outputs: [0; 16],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Strategy,
+    Tempo,
+    Steps,
+    Volume,
+    Crossfade,
+    Reverse,
+    Speed,
+    Stretch,
+    Granular,
+    Bank,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 10] = [
+        Setting::Strategy,
+        Setting::Tempo,
+        Setting::Steps,
+        Setting::Volume,
+        Setting::Crossfade,
+        Setting::Reverse,
+        Setting::Speed,
+        Setting::Stretch,
+        Setting::Granular,
+        Setting::Bank,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Setting::Strategy => "jump",
+            Setting::Tempo => "tempo",
+            Setting::Steps => "steps",
+            Setting::Volume => "volume",
+            Setting::Crossfade => "xfade",
+            Setting::Reverse => "reverse",
+            Setting::Speed => "speed",
+            Setting::Stretch => "stretch",
+            Setting::Granular => "grains",
+            Setting::Bank => "bank",
+        }
+    }
+}
+
+// Step `k` places along `len` things, wrapping at either end
+fn cycle(k: usize, len: usize, dir: i32) -> usize {
+    (k as i64 + dir as i64).rem_euclid(len as i64) as usize
+}
+
+impl Settings {
+    // How long each step lasts on a tape this long
+    pub fn chunk_len(&self, buffer_ms: u64) -> u64 {
+        match self.tempo {
+            // One step is a sixteenth note
+            Some(bpm) => 15_000 / bpm.max(1) as u64,
+            None => buffer_ms / self.steps.max(1) as u64,
+        }.max(1)
+    }
+
+    // Whether to play `step` backwards, having jumped here from `from` if we jumped
+    pub fn plays_back(&self, step: u32, from: Option<u32>) -> bool {
+        let marked: bool = step < MAX_STEPS && bit_at(&self.reversed_steps, step) != 0;
+        match self.reverse {
+            Reverse::Off => false,
+            Reverse::Marked => marked,
+            Reverse::Back => marked || from.is_some_and(|from| step < from),
+            Reverse::All => true,
+        }
+    }
+
+    // How fast `step` plays, its own speed on top of everyone's
+    pub fn speed_at(&self, step: u32) -> f32 {
+        let own: f32 = self.step_speeds.get(step as usize).copied().unwrap_or(1.0);
+        (self.speed * own).clamp(SPEED_RANGE.0, SPEED_RANGE.1)
+    }
+
+    // A speed that isn't a number would stop the tape dead, so it's left be
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed.is_finite() {
+            self.speed = speed.clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+        }
+    }
+
+    pub fn set_step_speed(&mut self, step: u32, speed: f32) {
+        if let Some(own) = self.step_speeds.get_mut(step as usize).filter(|_| speed.is_finite()) {
+            *own = speed.clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+        }
+    }
+
+    // How often `step` goes back to its start this time round, if at all.
+    // `wired` is a wire in the ratchet hole and `roll` is anything from 0 to 99.
+    pub fn repeat_ms(&self, step: u32, chunk_len: u64, wired: bool, roll: u32) -> Option<u64> {
+        let k: usize = step as usize;
+        if k >= MAX_STEPS as usize || roll >= self.repeat_chance[k] {
+            return None;
+        }
+        if self.stutters_ms[k] > 0 {
+            return Some((self.stutters_ms[k] as u64).min(chunk_len));
+        }
+        let times: u32 = if wired { self.ratchets[k].max(self.hole_ratchets) } else { self.ratchets[k] };
+        (times > 1).then(|| (chunk_len / times.min(MAX_RATCHETS) as u64).max(1))
+    }
+
+    pub fn show(&self, setting: Setting, banks: &[String]) -> String {
+        match setting {
+            Setting::Strategy => self.strategy.name().into(),
+            Setting::Tempo => self.tempo.map_or("tape".into(), |bpm| format!("{}bpm", bpm)),
+            Setting::Steps => self.steps.to_string(),
+            Setting::Volume => format!("{:.0}%", self.volume * 100.0),
+            Setting::Crossfade => format!("{}ms", self.crossfade_ms),
+            Setting::Reverse => self.reverse.name().into(),
+            Setting::Speed => format!("x{:.2}", self.speed),
+            Setting::Stretch => if self.stretch { "on" } else { "off" }.into(),
+            Setting::Granular => if self.granular { "on" } else { "off" }.into(),
+            Setting::Bank => banks.get(self.bank).map_or("?".into(), |b| bank_name(b).into()),
+        }
+    }
+
+    // Turn the volume up or down, stopping at either end
+    pub fn turn_volume(&mut self, dir: i32) {
+        self.volume = (self.volume + dir as f32 * VOLUME_STEP).clamp(0.0, VOLUME_MAX);
+    }
+
+    // Turn the tempo up or down from wherever the tape has it now
+    pub fn turn_tempo(&mut self, dir: i32, buffer_ms: u64) {
+        let (lo, hi) = TEMPO_RANGE;
+        let bpm: u32 = self.tempo.unwrap_or_else(|| {
+            let tape_bpm: u32 = (15_000 / self.chunk_len(buffer_ms)) as u32;
+            tape_bpm / TEMPO_STEP * TEMPO_STEP
+        });
+        self.tempo = Some((bpm as i64 + dir as i64 * TEMPO_STEP as i64).clamp(lo as i64, hi as i64) as u32);
+    }
+
+    // Move a setting one notch up (or down), going round at the ends
+    pub fn nudge(&mut self, setting: Setting, dir: i32, banks: usize) {
+        match setting {
+            Setting::Strategy => {
+                let k: usize = JumpStrategy::ALL.iter().position(|&s| s == self.strategy).unwrap_or(0);
+                self.strategy = JumpStrategy::ALL[cycle(k, JumpStrategy::ALL.len(), dir)];
+            }
+            Setting::Tempo => {
+                // The tape's own tempo sits before the slowest
+                let (lo, hi) = TEMPO_RANGE;
+                let notches: usize = ((hi - lo) / TEMPO_STEP + 2) as usize;
+                let k: usize = self.tempo.map_or(0, |bpm| ((bpm.clamp(lo, hi) - lo) / TEMPO_STEP + 1) as usize);
+                self.tempo = match cycle(k, notches, dir) {
+                    0 => None,
+                    n => Some(lo + (n as u32 - 1) * TEMPO_STEP),
+                };
+            }
+            Setting::Steps => {
+                let span: usize = (MAX_STEPS - MIN_STEPS + 1) as usize;
+                let k: usize = (self.steps.clamp(MIN_STEPS, MAX_STEPS) - MIN_STEPS) as usize;
+                self.steps = MIN_STEPS + cycle(k, span, dir) as u32;
+            }
+            Setting::Volume => {
+                let notches: usize = (VOLUME_MAX / VOLUME_STEP).round() as usize + 1;
+                let k: usize = (self.volume / VOLUME_STEP).round() as usize;
+                self.volume = cycle(k.min(notches - 1), notches, dir) as f32 * VOLUME_STEP;
+            }
+            Setting::Crossfade => {
+                let k: usize = CROSSFADES_MS.iter().position(|&ms| ms >= self.crossfade_ms).unwrap_or(0);
+                self.crossfade_ms = CROSSFADES_MS[cycle(k, CROSSFADES_MS.len(), dir)];
+            }
+            Setting::Reverse => {
+                let k: usize = Reverse::ALL.iter().position(|&r| r == self.reverse).unwrap_or(0);
+                self.reverse = Reverse::ALL[cycle(k, Reverse::ALL.len(), dir)];
+            }
+            Setting::Speed => {
+                // Anything past the fastest counts as the fastest
+                let k: usize = SPEEDS.iter().position(|&s| s >= self.speed - 0.01).unwrap_or(SPEEDS.len() - 1);
+                self.speed = SPEEDS[cycle(k, SPEEDS.len(), dir)];
+            }
+            Setting::Stretch => self.stretch = !self.stretch,
+            Setting::Granular => self.granular = !self.granular,
+            Setting::Bank => self.bank = cycle(self.bank, banks.max(1), dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nudges_wrap_around() {
+        let mut settings = Settings::default();
+        settings.nudge(Setting::Steps, 1, 1);
+        assert_eq!(settings.steps, MIN_STEPS);
+        settings.nudge(Setting::Steps, -1, 1);
+        assert_eq!(settings.steps, MAX_STEPS);
+
+        settings.nudge(Setting::Tempo, -1, 1);
+        assert_eq!(settings.tempo, Some(TEMPO_RANGE.1));
+        settings.nudge(Setting::Tempo, 1, 1);
+        assert_eq!(settings.tempo, None);
+
+        settings.nudge(Setting::Bank, 1, 3);
+        settings.nudge(Setting::Bank, 1, 3);
+        settings.nudge(Setting::Bank, 1, 3);
+        assert_eq!(settings.bank, 0);
+    }
+
+    #[test]
+    fn turning_stops_at_the_ends() {
+        let mut settings = Settings { volume: VOLUME_MAX, ..Settings::default() };
+        settings.turn_volume(1);
+        assert_eq!(settings.volume, VOLUME_MAX);
+        // A 1 second tape over 16 steps is about 240bpm
+        settings.turn_tempo(1, 1000);
+        assert_eq!(settings.tempo, Some(TEMPO_RANGE.1));
+        settings.turn_tempo(-2, 1000);
+        assert_eq!(settings.tempo, Some(TEMPO_RANGE.1 - 2 * TEMPO_STEP));
+    }
+
+    #[test]
+    fn reverses_what_the_mode_says() {
+        let mut settings = Settings { reversed_steps: 1 << 3, ..Settings::default() };
+        assert!(settings.plays_back(3, None));
+        assert!(!settings.plays_back(2, Some(9)));
+        settings.reverse = Reverse::Back;
+        assert!(settings.plays_back(2, Some(9)));
+        assert!(!settings.plays_back(9, Some(2)));
+        settings.reverse = Reverse::Off;
+        assert!(!settings.plays_back(3, None));
+        settings.nudge(Setting::Reverse, -1, 1);
+        assert_eq!(settings.reverse, Reverse::All);
+        assert!(settings.plays_back(9, None));
+    }
+
+    #[test]
+    fn steps_play_at_their_own_speed() {
+        let mut settings = Settings::default();
+        settings.set_step_speed(2, 0.5);
+        settings.set_step_speed(99, 0.5);
+        settings.nudge(Setting::Speed, 1, 1);
+        assert_eq!(settings.speed, 4.0 / 3.0);
+        assert!((settings.speed_at(2) - 2.0 / 3.0).abs() < 1e-6);
+        settings.set_speed(10.0);
+        assert_eq!(settings.speed_at(3), SPEED_RANGE.1);
+        settings.nudge(Setting::Speed, 1, 1);
+        assert_eq!(settings.speed, SPEEDS[0]);
+        settings.set_speed(f32::NAN);
+        settings.set_step_speed(2, f32::INFINITY);
+        assert_eq!((settings.speed, settings.step_speeds[2]), (SPEEDS[0], 0.5));
+    }
+
+    #[test]
+    fn steps_repeat_when_told_and_when_lucky() {
+        let mut settings = Settings { ratchet_hole: Some(15), hole_ratchets: 4, ..Settings::default() };
+        settings.ratchets[1] = 2;
+        settings.stutters_ms[2] = 30;
+        settings.repeat_chance[1] = 50;
+        assert_eq!(settings.repeat_ms(0, 400, false, 0), None);
+        assert_eq!(settings.repeat_ms(0, 400, true, 99), Some(100));
+        assert_eq!(settings.repeat_ms(1, 400, false, 49), Some(200));
+        assert_eq!(settings.repeat_ms(1, 400, false, 50), None);
+        assert_eq!(settings.repeat_ms(2, 400, true, 0), Some(30));
+        assert_eq!(settings.repeat_ms(2, 20, false, 0), Some(20));
+    }
+
+    #[test]
+    fn tempo_overrides_the_tape() {
+        let mut settings = Settings { steps: 8, ..Settings::default() };
+        assert_eq!(settings.chunk_len(4000), 500);
+        settings.tempo = Some(120);
+        assert_eq!(settings.chunk_len(4000), 125);
+    }
+}
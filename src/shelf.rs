@@ -0,0 +1,112 @@
+use rodio::Sink;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::deck::Deck;
+use crate::engine::WaveJumper;
+use crate::kit::{tape_letter, Kit};
+use crate::oled::Surface;
+use crate::tape::{get_banks, get_wav_from, TapeError, ASSETS_DIR};
+use crate::ui::Ui;
+
+// Every bank in ./assets/, the tapes and kits in the one we're playing from, and which of them is on
+pub struct Shelf {
+    banks: Vec<String>,
+    bank: usize,
+    waves: Vec<String>,
+    idx: usize,
+    kit: Kit,
+}
+
+// Find the first tape or kit from `start` that opens, skipping any duds.
+fn find_tape<S: Surface>(waves: &[String], start: usize, ui: &mut Ui<S>) -> Option<(usize, Kit)> {
+    for k in 0..waves.len() {
+        let idx: usize = (start + k) % waves.len();
+        match Kit::open(&waves[idx]) {
+            Ok(kit) => return Some((idx, kit)),
+            Err(e) => ui.report_bad_tape(&e),
+        }
+    }
+    None
+}
+
+// Swap whatever the sink is playing for `kit`, from the top
+fn play_kit(kit: &Kit, sink: &Sink, engine: &mut WaveJumper) {
+    if kit.pads.is_some() {
+        for (t, tape) in kit.tapes.iter().enumerate() {
+            say!("@{} is {}", tape_letter(t), tape.path);
+        }
+    }
+    sink.stop();
+    let deck: Deck = engine.load_kit(kit.clone());
+    // Spread over the outputs first, so every speaker gets its own echoes
+    sink.append(engine.effects().chain(engine.routing().route(deck)));
+}
+
+impl Shelf {
+    // Load the first thing in ./assets/ that plays, holding on a "no tapes" screen until something does.
+    // Only for starting up, once we're playing there's always the tape we had.
+    pub fn open<S: Surface>(ui: &mut Ui<S>, sink: &Sink, engine: &mut WaveJumper) -> Self {
+        loop {
+            let waves: Vec<String> = get_wav_from(ASSETS_DIR).unwrap_or_else(|e| {
+                warn!("@Can't read {} -- {}", ASSETS_DIR, e);
+                Vec::new()
+            });
+            if let Some((idx, kit)) = find_tape(&waves, 0, ui) {
+                play_kit(&kit, sink, engine);
+                return Shelf { banks: get_banks(), bank: 0, waves, idx, kit };
+            }
+            warn!("@Waiting for tapes -- {}", TapeError::NoTapes);
+            ui.no_tapes();
+            sleep(Duration::from_secs(2));
+        }
+    }
+
+    pub fn kit(&self) -> &Kit {
+        &self.kit
+    }
+
+    pub fn banks(&self) -> &[String] {
+        &self.banks
+    }
+
+    // The bank we're playing from, which the settings can be ahead of
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    // Pick up any banks added since we last looked
+    pub fn rescan(&mut self) {
+        self.banks = get_banks();
+    }
+
+    // Move `by` tapes along, backwards if negative, to the next that plays
+    pub fn skip<S: Surface>(&mut self, by: i32, ui: &mut Ui<S>, sink: &Sink, engine: &mut WaveJumper) {
+        let start: i64 = (self.idx as i64 + by as i64).rem_euclid(self.waves.len().max(1) as i64);
+        self.change(self.bank, self.waves.clone(), start as usize, ui, sink, engine);
+    }
+
+    // Over to the first tape that plays in the bank the settings point at
+    pub fn switch_bank<S: Surface>(&mut self, ui: &mut Ui<S>, sink: &Sink, engine: &mut WaveJumper) {
+        let bank: usize = engine.settings.bank.min(self.banks.len().saturating_sub(1));
+        let waves: Vec<String> = get_wav_from(&self.banks[bank]).unwrap_or_default();
+        self.change(bank, waves, 0, ui, sink, engine);
+    }
+
+    fn change<S: Surface>(&mut self, bank: usize, waves: Vec<String>, start: usize, ui: &mut Ui<S>, sink: &Sink, engine: &mut WaveJumper) {
+        match find_tape(&waves, start, ui) {
+            Some((idx, kit)) => {
+                play_kit(&kit, sink, engine);
+                (self.bank, self.waves, self.idx, self.kit) = (bank, waves, idx, kit);
+                sink.play();
+            }
+            None => {
+                // Nothing there plays, so keep on with the tape we had
+                warn!("@Nothing plays in {}, staying on {}", self.banks[bank], self.kit.path);
+                ui.nothing_plays();
+                engine.settings.bank = self.bank;
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind};
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::style::Print;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use rpi_pal::gpio::Level;
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{stdout, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::console;
+use crate::error::WaveJumperError;
+use crate::input::Button;
+use crate::framebuffer::FrameBuffer;
+use crate::mux::*;
+use crate::oled::Surface;
+use crate::patch::ChannelMap;
+use crate::pinio::MemGpio;
+use crate::uifb::*;
+
+// How long the virtual button is held down for a tap, and for a long press
+const BUTTON_TAP: Duration = Duration::from_millis(100);
+const BUTTON_HOLD: Duration = Duration::from_millis(900);
+// Gray code edges of one encoder click, and the time between them
+const ENCODER_EDGES: i32 = 4;
+const ENCODER_EDGE_GAP: Duration = Duration::from_millis(8);
+// Where the virtual encoder sits if the config doesn't have a real one
+const SIM_ENCODER_PINS: [u8; 3] = [12, 13, 19];
+// Log lines shown under the screen
+const LOG_LINES: usize = 6;
+
+// A breadboard that only exists in memory.
+// Wires run between a step on the demux row and a channel on the mux row.
+#[derive(Default)]
+pub struct Breadboard {
+    wires: Mutex<BTreeSet<(u32, u32)>>,
+    // When each button goes down and comes back up again
+    buttons: Mutex<Vec<(Button, Instant, Instant)>>,
+    // Where the encoder's gray code is, and the edges still to come
+    encoder: Mutex<(i32, Vec<(Instant, i32)>)>,
+    selected: Mutex<Option<u32>>,
+    screen: Mutex<Option<FrameBuffer>>,
+}
+
+impl Breadboard {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Breadboard::default())
+    }
+
+    // The config, with a virtual encoder on the panel if there isn't a real one
+    pub fn wire_config(config: &Config) -> Config {
+        let [a, b, push] = SIM_ENCODER_PINS;
+        Config {
+            encoder_a_pin: config.encoder_a_pin.or(Some(a)),
+            encoder_b_pin: config.encoder_b_pin.or(Some(b)),
+            encoder_button_pin: config.encoder_button_pin.or(Some(push)),
+            ..config.clone()
+        }
+    }
+
+    // Pins that behave as if this breadboard was plugged into them
+    pub fn gpio(self: &Arc<Self>, config: &Config) -> MemGpio {
+        let board: Arc<Breadboard> = Arc::clone(self);
+        let config: Config = config.clone();
+        MemGpio::with_resolver(Arc::new(move |levels: &HashMap<u8, Level>, pin: u8| {
+            board.resolve(levels, pin, &config)
+        }))
+    }
+
+    pub fn toggle_wire(&self, step: u32, channel: u32) {
+        let mut wires = self.wires.lock().unwrap();
+        if !wires.remove(&(step, channel)) {
+            wires.insert((step, channel));
+        }
+    }
+
+    fn hold_button(&self, which: Button, after: Duration, hold: Duration) {
+        let down: Instant = Instant::now() + after;
+        self.buttons.lock().unwrap().push((which, down, down + hold));
+    }
+
+    pub fn press(&self, which: Button) {
+        self.hold_button(which, Duration::ZERO, BUTTON_TAP);
+    }
+
+    pub fn long_press(&self, which: Button) {
+        self.hold_button(which, Duration::ZERO, BUTTON_HOLD);
+    }
+
+    pub fn double_press(&self, which: Button) {
+        self.hold_button(which, Duration::ZERO, BUTTON_TAP);
+        self.hold_button(which, BUTTON_TAP * 2, BUTTON_TAP);
+    }
+
+    // Queue up the four edges of a click, after any still to come
+    pub fn turn(&self, dir: i32) {
+        let mut encoder = self.encoder.lock().unwrap();
+        let mut at: Instant = encoder.1.last().map_or(Instant::now(), |&(t, _)| t);
+        for _ in 0..ENCODER_EDGES {
+            at += ENCODER_EDGE_GAP;
+            encoder.1.push((at, dir.signum()));
+        }
+    }
+
+    fn button_level(&self, which: Button) -> Level {
+        let now: Instant = Instant::now();
+        let mut buttons = self.buttons.lock().unwrap();
+        buttons.retain(|&(_, _, up)| now < up);
+        let held: bool = buttons.iter().any(|&(b, down, _)| b == which && down <= now);
+        if held { Level::Low } else { Level::High }
+    }
+
+    // Both encoder lines, sat high between clicks
+    fn encoder_levels(&self) -> (Level, Level) {
+        let now: Instant = Instant::now();
+        let mut encoder = self.encoder.lock().unwrap();
+        let (phase, edges) = &mut *encoder;
+        edges.retain(|&(t, dir)| {
+            if t <= now { *phase += dir; }
+            t > now
+        });
+        match phase.rem_euclid(ENCODER_EDGES) {
+            0 => (Level::High, Level::High),
+            1 => (Level::Low, Level::High),
+            2 => (Level::Low, Level::Low),
+            _ => (Level::High, Level::Low),
+        }
+    }
+
+    fn resolve(&self, levels: &HashMap<u8, Level>, pin: u8, config: &Config) -> Option<Level> {
+        if Some(pin) == config.button_pin {
+            return Some(self.button_level(Button::Main));
+        }
+        if Some(pin) == config.encoder_button_pin {
+            return Some(self.button_level(Button::Encoder));
+        }
+        if Some(pin) == config.encoder_a_pin {
+            return Some(self.encoder_levels().0);
+        }
+        if Some(pin) == config.encoder_b_pin {
+            return Some(self.encoder_levels().1);
+        }
+        let chip: usize = MUX_Z_PINS.iter().position(|&z| z == pin)?;
+
+        // Read the select lines just like a 74HC4051 would
+        let select = |pins: &[u8]| -> u32 {
+            pins.iter().enumerate().fold(0, |idx, (b, p)| {
+                idx | ((levels.get(p) == Some(&Level::High)) as u32) << b
+            })
+        };
+        // Our virtual board is wired like the schematic
+        let map = ChannelMap::default();
+        let channel: u32 = map.mux_hole(chip, select(&MUX_SELECT_PINS))?;
+        let enabled: Option<usize> = DEMUX_ENABLE_PINS.iter()
+            .position(|p| levels.get(p) == Some(&Level::Low));
+        let step: Option<u32> = enabled.and_then(|w| {
+            let driven: (usize, u32) = (w, select(&DEMUX_SELECT_PINS));
+            (0..16).find(|&p| map.demux_channel(p) == driven)
+        });
+
+        // Anything not wired up reads however it's pulled
+        let wired: bool = step.is_some_and(|p| self.wires.lock().unwrap().contains(&(p, channel)));
+        wired.then_some(Level::High)
+    }
+
+    // Which hole of which row sits under an oled pixel
+    fn hole_at(x: i32, y: i32) -> Option<(bool, u32)> {
+        let row_hit = |row_y: i32| (row_y - 1..=row_y + dot_ui_size as i32).contains(&y);
+        let k: i32 = (x - dot_ui_xpad as i32).div_euclid(dot_ui_xpad as i32);
+        if !(0..16).contains(&k) {
+            return None;
+        }
+        if row_hit(demux_ui_ycoord) {
+            Some((true, k as u32))
+        } else if row_hit(muxin_ui_ycoord) {
+            Some((false, k as u32))
+        } else {
+            None
+        }
+    }
+
+    // Demux first, then mux, to add or pull a wire
+    fn click(&self, x: i32, y: i32) {
+        let mut selected = self.selected.lock().unwrap();
+        match (Breadboard::hole_at(x, y), *selected) {
+            (Some((true, step)), _) => *selected = Some(step),
+            (Some((false, channel)), Some(step)) => {
+                self.toggle_wire(step, channel);
+                *selected = None;
+            }
+            _ => *selected = None,
+        }
+    }
+
+    fn render(&self) {
+        let screen = self.screen.lock().unwrap();
+        let Some(fb) = screen.as_ref() else { return };
+        let mut out = stdout().lock();
+        let _ = queue!(out, cursor::MoveTo(0, 0), Print(format!("┌{}┐\r\n", "─".repeat(128))));
+        for row in 0..32 {
+            let line: String = (0..128).map(|x| {
+                match (fb.pixel(x, row * 2), fb.pixel(x, row * 2 + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }
+            }).collect();
+            let _ = queue!(out, Print(format!("│{}│\r\n", line)));
+        }
+        let _ = queue!(out, Print(format!("└{}┘\r\n", "─".repeat(128))));
+
+        let wires: Vec<String> = self.wires.lock().unwrap().iter()
+            .map(|(p, c)| format!("{}>{}", p, c))
+            .collect();
+        let selected: String = self.selected.lock().unwrap()
+            .map_or("-".into(), |p| p.to_string());
+        let status: [String; 2] = [
+            format!("demux {} selected | wires {}", selected, wires.join(" ")),
+            "[click] patch  [space] tap  [m] hold  [d] double tap  [</>] turn  [enter] push  [c] clear  [q] quit".into(),
+        ];
+        for line in status.iter().chain(console::recent(LOG_LINES).iter()) {
+            let _ = queue!(out, terminal::Clear(terminal::ClearType::CurrentLine), Print(format!("{}\r\n", line)));
+        }
+        let _ = out.flush();
+    }
+}
+
+// Hand the terminal back the way we found it
+pub fn restore_terminal() {
+    let _ = execute!(stdout(), event::DisableMouseCapture, terminal::LeaveAlternateScreen, cursor::Show);
+    let _ = terminal::disable_raw_mode();
+    console::release();
+}
+
+// Keyboard and mouse, on their own thread so the main loop never waits on us
+fn listen(board: Arc<Breadboard>) {
+    loop {
+        let Ok(ev) = event::read() else { continue };
+        match ev {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+                KeyCode::Char(' ') | KeyCode::Char('s') => board.press(Button::Main),
+                KeyCode::Char('m') => board.long_press(Button::Main),
+                KeyCode::Char('d') => board.double_press(Button::Main),
+                KeyCode::Enter => board.press(Button::Encoder),
+                KeyCode::Left => board.turn(-1),
+                KeyCode::Right => board.turn(1),
+                KeyCode::Char('c') => board.wires.lock().unwrap().clear(),
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                _ => {}
+            },
+            Event::Mouse(m) if m.kind == MouseEventKind::Down(MouseButton::Left) => {
+                // One character is one pixel wide and two pixels tall, inside a border
+                let x: i32 = m.column as i32 - 1;
+                let y: i32 = (m.row as i32 - 1) * 2;
+                board.click(x, y);
+            }
+            _ => {}
+        }
+        board.render();
+    }
+    restore_terminal();
+    std::process::exit(0);
+}
+
+// Draws the oled into the terminal, half a character per pixel
+pub struct TermSurface {
+    fb: FrameBuffer,
+    board: Arc<Breadboard>,
+}
+
+impl TermSurface {
+    pub fn open(board: Arc<Breadboard>) -> Result<Self, WaveJumperError> {
+        let term_err = |e: std::io::Error| WaveJumperError::Display(format!("terminal: {}", e));
+        terminal::enable_raw_mode().map_err(term_err)?;
+        execute!(stdout(), terminal::EnterAlternateScreen, event::EnableMouseCapture, cursor::Hide)
+            .map_err(term_err)?;
+        console::capture();
+
+        // Don't leave the terminal in raw mode if we fall over
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            hook(info);
+        }));
+
+        let listener: Arc<Breadboard> = Arc::clone(&board);
+        thread::spawn(move || listen(listener));
+        Ok(TermSurface { fb: FrameBuffer::oled(), board })
+    }
+}
+
+impl OriginDimensions for TermSurface {
+    fn size(&self) -> Size {
+        self.fb.size()
+    }
+}
+
+impl DrawTarget for TermSurface {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.fb.draw_iter(pixels)
+    }
+}
+
+impl Surface for TermSurface {
+    fn flush(&mut self) -> Result<(), WaveJumperError> {
+        *self.board.screen.lock().unwrap() = Some(self.fb.clone());
+        self.board.render();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patch_bay_reads_virtual_wires() {
+        let board = Breadboard::new();
+        let config = Config { mux_settle: Duration::ZERO, ..Config::default() };
+        let gpio: MemGpio = board.gpio(&config);
+        let mut bay = PatchBay::new(&gpio, &config).unwrap();
+        board.toggle_wire(3, 0);
+        board.toggle_wire(3, 12);
+        board.toggle_wire(9, 5);
+        assert_eq!(bay.scan(3).unwrap(), (1 << 0) | (1 << 12));
+        assert_eq!(bay.scan(9).unwrap(), 1 << 5);
+        assert_eq!(bay.scan(4).unwrap(), 0);
+        board.toggle_wire(9, 5);
+        assert_eq!(bay.scan(9).unwrap(), 0);
+    }
+
+    #[test]
+    fn channel_map_follows_the_wiring() {
+        let board = Breadboard::new();
+        let mut config = Config { mux_settle: Duration::ZERO, ..Config::default() };
+        // As if mux holes 0 and 1 had their wires swapped on the chip
+        config.channel_map.mux.swap(0, 1);
+        let gpio: MemGpio = board.gpio(&config);
+        let mut bay = PatchBay::new(&gpio, &config).unwrap();
+        board.toggle_wire(3, 0);
+        assert_eq!(bay.scan(3).unwrap(), 1 << 1);
+    }
+
+    #[test]
+    fn scanner_keeps_up_with_the_wires() {
+        let board = Breadboard::new();
+        let config = Config { mux_settle: Duration::ZERO, ..Config::default() };
+        let gpio: MemGpio = board.gpio(&config);
+        let patch = PatchBay::new(&gpio, &config).unwrap().spawn();
+        board.toggle_wire(3, 0);
+        board.toggle_wire(4, 9);
+        board.toggle_wire(10, 5);
+        patch.follow(3);
+        // The step after ours gets looked at too, and the rest of the board in time
+        let deadline: Instant = Instant::now() + Duration::from_secs(2);
+        while (patch.row(3), patch.row(4), patch.row(10)) != (1, 1 << 9, 1 << 5) {
+            assert!(Instant::now() < deadline, "scanner never caught up");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn clicks_patch_demux_to_mux() {
+        let board = Breadboard::new();
+        let (step, channel): (i32, i32) = (2, 7);
+        board.click(dot_ui_xpad as i32 * (step + 1) + 1, demux_ui_ycoord + 2);
+        board.click(dot_ui_xpad as i32 * (channel + 1) + 1, muxin_ui_ycoord + 2);
+        assert!(board.wires.lock().unwrap().contains(&(2, 7)));
+    }
+}
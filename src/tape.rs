@@ -0,0 +1,149 @@
+use rodio::decoder::{DecoderBuilder, DecoderError};
+use rodio::Source;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{read_dir, File, ReadDir};
+use std::io;
+use std::sync::Arc;
+
+use crate::kit::KIT_EXT;
+
+// Everything that can go wrong between ./assets/ and the sink
+#[derive(Debug)]
+pub enum TapeError {
+    NoTapes,
+    Missing(String, io::Error),
+    Corrupt(String, DecoderError),
+    Silent(String),
+    BadKit(String, String),
+}
+
+impl fmt::Display for TapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeError::NoTapes => write!(f, "no wavs found in ./assets/"),
+            TapeError::Missing(mf, e) => write!(f, "{} can't be read: {}", mf, e),
+            TapeError::Corrupt(mf, e) => write!(f, "{} can't be decoded: {}", mf, e),
+            TapeError::Silent(mf) => write!(f, "{} has no audio frames", mf),
+            TapeError::BadKit(mf, why) => write!(f, "{} isn't a kit: {}", mf, why),
+        }
+    }
+}
+
+impl std::error::Error for TapeError {}
+
+// Where the tapes live. Each folder inside is a bank of its own.
+pub const ASSETS_DIR: &str = "./assets/";
+
+// How finely we remember the shape of a tape
+pub const PEAK_BINS: usize = 256;
+
+// A wav we have opened, decoded and know we can play
+#[derive(Clone)]
+pub struct Tape {
+    pub path: String,
+    pub duration_ms: u64,
+    // Loudest sample in each slice of the tape, 255 being the loudest of the lot
+    pub peaks: Vec<u8>,
+    // Every sample, channels interleaved
+    pub samples: Arc<[f32]>,
+    pub channels: u16,
+    pub rate: u32,
+}
+
+impl Tape {
+    pub fn open(mf: &str) -> Result<Self, TapeError> {
+        let source = get_decoded_wav(mf)?
+            .build()
+            .map_err(|e| TapeError::Corrupt(mf.into(), e))?;
+        let channels: u16 = source.channels().max(1);
+        let rate: u32 = source.sample_rate().max(1);
+        let samples: Arc<[f32]> = source.collect();
+
+        // We count the frames ourselves, headers have been known to lie
+        let frames: u64 = samples.len() as u64 / channels as u64;
+        let duration_ms: u64 = frames * 1000 / rate as u64;
+        if duration_ms == 0 {
+            return Err(TapeError::Silent(mf.into()));
+        }
+        let peaks = get_peaks(&samples, channels, PEAK_BINS);
+        Ok(Tape { path: mf.to_string(), duration_ms, peaks, samples, channels, rate })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+}
+
+pub fn get_wav_from_local_assets() -> Result<Vec<String>, io::Error> {
+    get_wav_from(ASSETS_DIR)
+}
+
+// Every wav in a bank, which is any folder really, and any kit laying them out
+pub fn get_wav_from(dir: &str) -> Result<Vec<String>, io::Error> {
+    let entries: ReadDir = read_dir(dir)?;
+    let ok_entries = entries.filter_map(|res| res.ok());
+    let paths = ok_entries.map(|e| e.path());
+
+    let waves = paths.filter(|p| {
+        let ext: Option<&OsStr> = p.extension();
+        let ext_str: Option<&str> = ext.and_then(|e| e.to_str());
+        ext_str.is_some_and(|e| e.eq_ignore_ascii_case("wav") || e.eq_ignore_ascii_case(KIT_EXT))
+    });
+
+    let mut wav_string: Vec<String> = waves.map(|p| p.to_string_lossy().into_owned()).collect();
+    wav_string.sort();
+    Ok(wav_string)
+}
+
+// ./assets/ itself, then its folders
+pub fn get_banks() -> Vec<String> {
+    let mut banks: Vec<String> = read_dir(ASSETS_DIR)
+        .map(|entries| {
+            entries.filter_map(|res| res.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .map(|p| format!("{}/", p.to_string_lossy()))
+                .collect()
+        })
+        .unwrap_or_default();
+    banks.sort();
+    banks.insert(0, ASSETS_DIR.into());
+    banks
+}
+
+// The short name of a bank, for the oled
+pub fn bank_name(bank: &str) -> &str {
+    bank.trim_end_matches('/').rsplit('/').next().unwrap_or(bank)
+}
+
+pub fn get_decoded_wav(mf: &str) -> Result<DecoderBuilder<File>, TapeError> {
+    let file = File::open(mf).map_err(|e| TapeError::Missing(mf.into(), e))?;
+    let len = file.metadata().map_err(|e| TapeError::Missing(mf.into(), e))?.len();
+    Ok(DecoderBuilder::new()
+        .with_data(file)
+        .with_hint("wav")
+        .with_byte_len(len)
+        .with_seekable(true))
+}
+
+// The peak envelope of some interleaved samples, split evenly over `bins` slices
+pub fn get_peaks(samples: &[f32], channels: u16, bins: usize) -> Vec<u8> {
+    let frames: usize = (samples.len() / channels.max(1) as usize).max(1);
+    let mut peaks: Vec<f32> = vec![0.0; bins];
+    for (k, sample) in samples.iter().enumerate() {
+        let frame: usize = k / channels.max(1) as usize;
+        let bin: usize = (frame * bins / frames).min(bins.saturating_sub(1));
+        if let Some(peak) = peaks.get_mut(bin) {
+            *peak = peak.max(sample.abs());
+        }
+    }
+
+    // Quiet tapes deserve to be seen too
+    let loudest: f32 = peaks.iter().copied().fold(0.0, f32::max);
+    if loudest <= 0.0 {
+        return vec![0; bins];
+    }
+    peaks.iter().map(|p| (p / loudest * 255.0).round() as u8).collect()
+}
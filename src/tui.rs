@@ -0,0 +1,140 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::console;
+use crate::control::{Command, Status};
+use crate::jump::bit_at;
+
+// How often we redraw while waiting on keys
+const FRAME_TIME: Duration = Duration::from_millis(50);
+const VOLUME_STEP: f32 = 0.1;
+
+// Take over the terminal and start drawing on our own thread
+pub fn open(sender: Sender<Command>, status: Arc<Mutex<Status>>) {
+    console::capture();
+    let terminal: DefaultTerminal = ratatui::init();
+    thread::spawn(move || listen(terminal, sender, status));
+}
+
+// Hand the terminal back, along with anything that was logged meanwhile
+pub fn close() {
+    ratatui::restore();
+    console::release();
+}
+
+fn listen(mut terminal: DefaultTerminal, sender: Sender<Command>, status: Arc<Mutex<Status>>) {
+    // Show how sure we are of each wire instead of the wires themselves
+    let mut diagnose: bool = false;
+    loop {
+        let snapshot: Status = status.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let _ = terminal.draw(|frame| draw(frame, &snapshot, diagnose));
+
+        if !event::poll(FRAME_TIME).unwrap_or(false) {
+            continue;
+        }
+        let Ok(Event::Key(key)) = event::read() else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let cmd: Option<Command> = match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Command::Quit),
+            KeyCode::Char(' ') | KeyCode::Char('s') => Some(Command::Skip),
+            KeyCode::Char('p') => Some(Command::TogglePause),
+            KeyCode::Char('j') => Some(Command::NextStrategy),
+            KeyCode::Char('d') => {
+                diagnose = !diagnose;
+                None
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => Some(Command::Volume(VOLUME_STEP)),
+            KeyCode::Char('-') => Some(Command::Volume(-VOLUME_STEP)),
+            KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+            _ => None,
+        };
+        if let Some(cmd) = cmd {
+            // The instrument has gone away, nothing left for us to do
+            if sender.send(cmd).is_err() || cmd == Command::Quit {
+                return;
+            }
+        }
+    }
+}
+
+// A row of holes like the ones on the breadboard
+fn hole_row(label: &str, lit: impl Fn(u32) -> bool) -> Line<'static> {
+    let holes: String = (0..16).map(|k| if lit(k) { "● " } else { "○ " }).collect();
+    Line::from(vec![Span::raw(format!("{:<6}", label)), Span::raw(holes)])
+}
+
+// One hole to hole connection in the patch view
+fn wire_cell(status: &Status, step: usize, k: u32, diagnose: bool) -> String {
+    let connected: bool = bit_at(&status.patch[step], k) != 0;
+    let flaky: bool = bit_at(&status.flaky[step], k) != 0;
+    match (flaky, diagnose) {
+        // Tens of percent sure, 9 being the best a flaky wire gets
+        (true, true) => format!("{:<2}", (status.confidence[step][k as usize] / 10).min(9)),
+        (true, false) => "▒ ".into(),
+        (false, _) if connected => "█ ".into(),
+        (false, _) => "· ".into(),
+    }
+}
+
+fn draw(frame: &mut Frame, status: &Status, diagnose: bool) {
+    let [header, gauge, patch, log, help]: [Rect; 5] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(1),
+        Constraint::Length(21),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+
+    let state: &str = if status.paused { "paused" } else { "playing" };
+    let info = Line::from(format!(
+        "bpm {} | jump {} | vol {:.0}% | {}",
+        status.bpm(), status.strategy.name(), status.volume * 100.0, state
+    ));
+    let title = format!(" wave_jumper -- {} ", status.tape);
+    frame.render_widget(Paragraph::new(info).block(Block::bordered().title(title)), header);
+
+    let ratio: f64 = status.tape_ms as f64 / status.buffer_ms.max(1) as f64;
+    let label = format!("{:08}ms / {:08}ms", status.tape_ms, status.buffer_ms);
+    frame.render_widget(Gauge::default().ratio(ratio.clamp(0.0, 1.0)).label(label), gauge);
+
+    // The oled's two rows, then every wire we know about
+    let mut lines: Vec<Line> = vec![
+        hole_row("demux", |k| k == status.position),
+        hole_row("mux", |k| bit_at(&status.mux_word, k) != 0),
+        Line::from(format!("{:<6}{}", "", (0..16).map(|k| format!("{:<2}", k % 10)).collect::<String>())),
+    ];
+    for step in 0..status.patch.len() {
+        let wires: String = (0..16).map(|k| wire_cell(status, step, k, diagnose)).collect();
+        let style: Style = if step as u32 == status.position {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::styled(format!("{:<6}{}", format!("x{:02}", step), wires), style));
+    }
+    let flaky: u32 = status.flaky.iter().map(|w| w.count_ones()).sum();
+    let title: String = match (diagnose, flaky) {
+        (false, 0) => " patch ".into(),
+        (false, n) => format!(" patch -- {} loose, [d] to diagnose ", n),
+        (true, n) => format!(" patch -- {} loose, % sure ", n),
+    };
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), patch);
+
+    let rows: usize = log.height.saturating_sub(2) as usize;
+    let log_lines: Vec<Line> = console::recent(rows).into_iter().map(Line::from).collect();
+    frame.render_widget(Paragraph::new(log_lines).block(Block::bordered().title(" log ")), log);
+
+    let keys = "[space] skip  [p] pause  [j] jump strategy  [+/-] volume  [d] diagnose  [q] quit";
+    frame.render_widget(Paragraph::new(keys), help);
+}
@@ -0,0 +1,218 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::engine::StepOutcome;
+use crate::error::WaveJumperError;
+use crate::input::InputEvent;
+use crate::jump::bit_at;
+use crate::kit::Kit;
+use crate::menu::{Menu, MenuEvent, Outcome};
+use crate::oled::{Brush, Display, Surface};
+use crate::settings::{Settings, MAX_STEPS};
+use crate::tape::TapeError;
+use crate::uifb::*;
+
+// The show must go on, a hiccup on the oled or gpio is only worth a grumble.
+pub fn grumble(res: Result<(), WaveJumperError>) {
+    if let Err(e) = res {
+        warn!("@Warning -- {}", e);
+    }
+}
+
+// A kit shows which tape each step plays rather than any one tape's wave
+fn strip<'a>(letters: &'a Option<[char; MAX_STEPS as usize]>, peaks: &'a [u8], playhead: f32) -> Strip<'a> {
+    match letters.as_ref() {
+        Some(letters) => Strip::Kit(letters),
+        None => Strip::Wave(peaks, playhead),
+    }
+}
+
+// Everything on the oled while we play: the tape, the dots and wires of the patch, and the menu over the top
+pub struct Ui<S: Surface> {
+    oled: Display<S>,
+    demux_dots: Vec<Dot>,
+    muxin_dots: Vec<Dot>,
+    // The demux dot we last filled in
+    lit: usize,
+    links: Vec<Link>,
+    menu: Option<Menu>,
+    // What's loaded, and what goes under the demux row for it
+    title: String,
+    letters: Option<[char; MAX_STEPS as usize]>,
+    peaks: Vec<u8>,
+    playhead: f32,
+}
+
+impl<S: Surface> Ui<S> {
+    pub fn new(oled: Display<S>) -> Self {
+        Ui {
+            oled,
+            demux_dots: get_dot_row(demux_ui_ycoord, dot_ui_size, dot_ui_xpad, MAX_STEPS as usize),
+            muxin_dots: get_dot_row(muxin_ui_ycoord, dot_ui_size, dot_ui_xpad, MAX_STEPS as usize),
+            lit: 0,
+            links: Vec::new(),
+            menu: None,
+            title: String::new(),
+            letters: None,
+            peaks: Vec::new(),
+            playhead: 0.0,
+        }
+    }
+
+    pub fn oled(&self) -> &Display<S> {
+        &self.oled
+    }
+
+    // Clear away whatever we showed while looking for tapes and lay out the patch
+    pub fn start(&mut self, kit: &Kit) {
+        grumble(self.oled.clear());
+        self.load(kit);
+        grumble(draw_dot_row(&mut self.oled, &mut self.demux_dots));
+        grumble(draw_dot_row(&mut self.oled, &mut self.muxin_dots));
+    }
+
+    // Put up a freshly loaded tape or kit, or the one we stayed on
+    pub fn load(&mut self, kit: &Kit) {
+        self.title = kit.path.clone();
+        self.letters = kit.letters();
+        self.peaks = kit.tapes[0].peaks.clone();
+        grumble(show_title(&mut self.oled, &self.title));
+    }
+
+    pub fn report_bad_tape(&mut self, err: &TapeError) {
+        warn!("@Skipping tape -- {}", err);
+        grumble(show_title(&mut self.oled, "bad tape, skipping.."));
+        grumble(self.oled.paint());
+        // Give the user a moment to read the oled
+        sleep(Duration::from_millis(500));
+    }
+
+    pub fn no_tapes(&mut self) {
+        grumble(self.oled.clear());
+        grumble(self.oled.text(5, 20, "no tapes :("));
+        grumble(self.oled.text(5, 40, "add wavs to assets"));
+        grumble(self.oled.paint());
+    }
+
+    pub fn nothing_plays(&mut self) {
+        grumble(show_title(&mut self.oled, "nothing plays there"));
+        grumble(self.oled.paint());
+        sleep(Duration::from_millis(500));
+    }
+
+    // Light up the step we're on and its wires, and catch up a freshly plugged in oled.
+    // `playhead` runs from 0 at the top of the tape to 1 at the end.
+    pub fn show_step(&mut self, out: &StepOutcome, steps: u32, playhead: f32, settings: &Settings, banks: &[String]) {
+        self.playhead = playhead;
+        grumble(clear_dot(&mut self.oled, &mut self.demux_dots[self.lit]));
+        self.lit = out.position as usize;
+        grumble(fill_dot(&mut self.oled, &mut self.demux_dots[self.lit]));
+
+        for (i, dot) in self.muxin_dots.iter_mut().enumerate() {
+            let cache: Dot = *dot;
+            dot.lv = if bit_at(&out.mux_word, i as u32) != 0 { DotLevel::High } else { DotLevel::Low };
+            if *dot != cache {
+                grumble(match dot.lv {
+                    DotLevel::High => fill_dot(&mut self.oled, dot),
+                    DotLevel::Low => clear_dot(&mut self.oled, dot),
+                });
+            }
+        }
+
+        // Keep a line on the oled for every wire on the board, not just the ones from here
+        let wanted: Vec<Link> = get_links(&out.rows, &self.demux_dots[..steps as usize], &self.muxin_dots[..steps as usize]);
+        let before: usize = self.links.len();
+        let oled: &mut Display<S> = &mut self.oled;
+        self.links.retain(|lk| {
+            if wanted.contains(lk) { true } else {
+                grumble(oled.line(lk.a.x, line_ui_ystart, lk.b.x, line_ui_yend, Some(Brush::Eraser)));
+                false
+            }
+        });
+        // Rubbing one line out can nick the ones it crossed
+        let redraw: bool = self.links.len() < before;
+        for lk in wanted {
+            if redraw || !self.links.contains(&lk) {
+                grumble(self.oled.line(lk.a.x, line_ui_ystart, lk.b.x, line_ui_yend, None));
+            }
+            if !self.links.contains(&lk) {
+                self.links.push(lk);
+            }
+        }
+
+        // The menu sits on top of everything while it's open
+        if self.oled.reattach() && self.menu.is_none() {
+            self.redraw();
+        } else {
+            grumble(show_strip(&mut self.oled, strip(&self.letters, &self.peaks, self.playhead), steps));
+            self.draw_menu(settings, banks);
+        }
+    }
+
+    // Paint the whole UI from scratch
+    fn redraw(&mut self) {
+        let demux_dot: Dot = self.demux_dots[self.lit];
+        grumble(redraw_ui(&mut self.oled, &self.title, strip(&self.letters, &self.peaks, self.playhead), &demux_dot, &self.muxin_dots, &self.links));
+    }
+
+    pub fn menu_open(&self) -> bool {
+        self.menu.is_some()
+    }
+
+    pub fn open_menu(&mut self) {
+        self.menu = Some(Menu::new());
+    }
+
+    // Hand an input to the menu, putting the patch back up once it closes
+    pub fn work_menu(&mut self, ev: InputEvent, settings: &mut Settings, banks: &[String]) -> Outcome {
+        let Some(menu) = self.menu.as_mut() else { return Outcome::Closed };
+        let outcome: Outcome = menu.handle(MenuEvent::from_input(ev), settings, banks);
+        if outcome == Outcome::Closed {
+            self.menu = None;
+            self.redraw();
+        }
+        outcome
+    }
+
+    pub fn draw_menu(&mut self, settings: &Settings, banks: &[String]) {
+        if let Some(m) = self.menu.as_ref() {
+            grumble(m.draw(&mut self.oled, settings, banks));
+        }
+        grumble(self.oled.paint());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::FrameBuffer;
+
+    fn step(position: u32, rows: [u16; 16]) -> StepOutcome {
+        StepOutcome {
+            position, seek_ms: None, tape: 0, mux_word: rows[position as usize], rows, backwards: false,
+            speed: 1.0, volume: 1.0, repeat_ms: None, jump_to: None, chunk_len: 100,
+        }
+    }
+
+    fn filled(ui: &Ui<FrameBuffer>, dot: &Dot) -> bool {
+        ui.oled().surface().pixel((dot.x + 3) as u32, (dot.y + 3) as u32)
+    }
+
+    #[test]
+    fn steps_move_the_lit_dot_along() {
+        let mut ui = Ui::new(Display::new(FrameBuffer::oled()));
+        let mut rows: [u16; 16] = [0; 16];
+        rows[2] = 1 << 5;
+        ui.show_step(&step(2, rows), 16, 0.0, &Settings::default(), &[]);
+        assert!(filled(&ui, &ui.demux_dots[2]));
+        assert!(filled(&ui, &ui.muxin_dots[5]));
+        assert_eq!(ui.links.len(), 1);
+
+        ui.show_step(&step(3, rows), 16, 0.0, &Settings::default(), &[]);
+        assert!(!filled(&ui, &ui.demux_dots[2]));
+        assert!(filled(&ui, &ui.demux_dots[3]));
+        // Nothing comes out of step 3 but the wire out of step 2 is still on the board
+        assert!(!filled(&ui, &ui.muxin_dots[5]));
+        assert_eq!(ui.links.len(), 1);
+    }
+}
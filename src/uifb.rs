@@ -1,6 +1,25 @@
 use std::ops::Not;
 use rpi_pal::gpio::Level;
 
+use crate::error::WaveJumperError;
+use crate::oled::{Brush, Display, Surface};
+
+// Some UI decisions.
+pub const title_ui_ycoord: i32 = 5;
+pub const title_ui_ysize: u32 = 8;
+pub const demux_ui_ycoord: i32 = (title_ui_ycoord as u32 + title_ui_ysize + 2) as i32;
+pub const muxin_ui_ycoord: i32 = 55;
+pub const dot_ui_size: u32 = 7;
+pub const dot_ui_xpad: u32 = dot_ui_size;
+// The waveform sits under the demux row, one dot's width per chunk
+pub const wave_ui_xcoord: i32 = dot_ui_xpad as i32;
+pub const wave_ui_ycoord: i32 = demux_ui_ycoord + dot_ui_size as i32 + 2;
+pub const wave_ui_ysize: u32 = 11;
+// Where a kit's letters sit on the line, the font's ascent down from the top of the strip
+pub const kit_ui_baseline: i32 = 8;
+pub const line_ui_ystart: i32 = wave_ui_ycoord + wave_ui_ysize as i32 + 2;
+pub const line_ui_yend: i32 = muxin_ui_ycoord - 5;
+
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum DotLevel {
      High,
@@ -77,3 +96,200 @@ pub struct Link {
     pub a: Dot,
     pub b: Dot,
 }
+
+// A link for every wire in the patch, `rows` being the wires out of each demux dot
+pub fn get_links(rows: &[u16], demux_dots: &[Dot], muxin_dots: &[Dot]) -> Vec<Link> {
+    let mut links: Vec<Link> = Vec::new();
+    for (r, a) in demux_dots.iter().enumerate() {
+        let word: u16 = rows.get(r).copied().unwrap_or(0);
+        for (c, b) in muxin_dots.iter().enumerate() {
+            if word >> c & 1 == 1 {
+                links.push(Link { a: Dot { lv: DotLevel::High, ..*a }, b: Dot { lv: DotLevel::High, ..*b } });
+            }
+        }
+    }
+    links
+}
+
+pub fn get_dot_row(doty: i32, size: u32, pad: u32, num: usize) -> Vec<Dot> {
+    let mut dots: Vec<Dot> = Vec::new();
+    let mut dotx: i32 = 0; 
+    for _ in 0..num {
+        dotx = (dotx as u32 + pad) as i32;
+        dots.push(Dot{x: dotx, y: doty, sz: size, lv: DotLevel::Low});
+    }
+    dots
+}
+
+pub fn clear_dot<S: Surface>(oled: &mut Display<S>, dot: &mut Dot) -> Result<(), WaveJumperError> {
+    dot.lv = DotLevel::Low;
+    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Eraser))?;
+    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen))
+}
+
+pub fn fill_dot<S: Surface>(oled: &mut Display<S>, dot: &mut Dot) -> Result<(), WaveJumperError> {
+    dot.lv = DotLevel::High;
+    oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Marker))
+}
+
+pub fn show_title<S: Surface>(oled: &mut Display<S>, txt: &str) -> Result<(), WaveJumperError> {
+    oled.rect(0, 0, 128, title_ui_ysize, Some(Brush::Eraser))?;
+    oled.text(5, title_ui_ycoord, txt)
+}
+
+// The tape's peak envelope with a tick between chunks and a line where we're playing.
+// `playhead` runs from 0 at the top of the tape to 1 at the end.
+pub fn show_wave<S: Surface>(oled: &mut Display<S>, peaks: &[u8], chunks: u32, playhead: f32) -> Result<(), WaveJumperError> {
+    let width: u32 = chunks * dot_ui_xpad;
+    let top: i32 = wave_ui_ycoord;
+    let bottom: i32 = wave_ui_ycoord + wave_ui_ysize as i32 - 1;
+    let mid: i32 = wave_ui_ycoord + wave_ui_ysize as i32 / 2;
+    oled.rect(wave_ui_xcoord, top, width, wave_ui_ysize, Some(Brush::Eraser))?;
+
+    for col in 0..width as usize {
+        // Squash as many peaks as land in this column into one
+        let first: usize = col * peaks.len() / width as usize;
+        let last: usize = ((col + 1) * peaks.len() / width as usize).max(first + 1);
+        let peak: u8 = peaks.get(first..last.min(peaks.len())).and_then(|p| p.iter().max().copied()).unwrap_or(0);
+        // Keep the top and bottom rows free for the chunk ticks
+        let reach: i32 = peak as i32 * (wave_ui_ysize as i32 / 2 - 1) / 255;
+        let x: i32 = wave_ui_xcoord + col as i32;
+        oled.line(x, mid - reach, x, mid + reach, Some(Brush::Pencil))?;
+    }
+
+    for k in 0..=chunks {
+        let x: i32 = wave_ui_xcoord + (k * dot_ui_xpad) as i32 - (k == chunks) as i32;
+        oled.line(x, top, x, top, Some(Brush::Pencil))?;
+        oled.line(x, bottom, x, bottom, Some(Brush::Pencil))?;
+    }
+
+    // Cut a gap around the playhead so it stands out from the wave
+    let x: i32 = wave_ui_xcoord + (playhead.clamp(0.0, 1.0) * (width - 1) as f32) as i32;
+    oled.line(x, top, x, bottom, Some(Brush::Eraser))?;
+    oled.line(x, top, x, bottom, Some(Brush::Pencil))
+}
+
+// A letter under each step for the tape it plays, A being the kit's first
+pub fn show_kit<S: Surface>(oled: &mut Display<S>, letters: &[char], chunks: u32) -> Result<(), WaveJumperError> {
+    oled.rect(wave_ui_xcoord, wave_ui_ycoord, chunks * dot_ui_xpad, wave_ui_ysize, Some(Brush::Eraser))?;
+    for (k, letter) in letters.iter().take(chunks as usize).enumerate() {
+        let x: i32 = wave_ui_xcoord + (k as u32 * dot_ui_xpad) as i32;
+        oled.text(x, wave_ui_ycoord + kit_ui_baseline, &letter.to_string())?;
+    }
+    Ok(())
+}
+
+// What goes under the demux row
+#[derive(Debug, Clone, Copy)]
+pub enum Strip<'a> {
+    // The tape's peaks and where we're playing
+    Wave(&'a [u8], f32),
+    // Which tape each step of a kit plays
+    Kit(&'a [char]),
+}
+
+pub fn show_strip<S: Surface>(oled: &mut Display<S>, strip: Strip, chunks: u32) -> Result<(), WaveJumperError> {
+    match strip {
+        Strip::Wave(peaks, playhead) => show_wave(oled, peaks, chunks, playhead),
+        Strip::Kit(letters) => show_kit(oled, letters, chunks),
+    }
+}
+
+// Paint the whole UI from scratch, say when an oled turns up mid performance
+pub fn redraw_ui<S: Surface>(oled: &mut Display<S>, title: &str, strip: Strip, demux_dot: &Dot, muxin_dots: &[Dot], links: &[Link]) -> Result<(), WaveJumperError> {
+    oled.clear()?;
+    show_title(oled, title)?;
+    show_strip(oled, strip, muxin_dots.len() as u32)?;
+    for dot in muxin_dots.iter() {
+        let brush: Brush = if dot.is_high() { Brush::Marker } else { Brush::Pen };
+        oled.circle(dot.x, dot.y, dot.sz, Some(brush))?;
+    }
+    for dot in get_dot_row(demux_ui_ycoord, dot_ui_size, dot_ui_xpad, muxin_dots.len()) {
+        oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen))?;
+    }
+    oled.circle(demux_dot.x, demux_dot.y, demux_dot.sz, Some(Brush::Marker))?;
+    for lk in links.iter() {
+        oled.line(lk.a.x, line_ui_ystart, lk.b.x, line_ui_yend, None)?;
+    }
+    oled.paint()
+}
+
+pub fn draw_dot_row<S: Surface>(oled: &mut Display<S>, dots: &mut [Dot]) -> Result<(), WaveJumperError> {
+    for dot in dots.iter_mut() {
+        dot.lv = DotLevel::Low;
+        oled.circle(dot.x, dot.y, dot.sz, Some(Brush::Pen))?;
+    }
+    Ok(())
+}
+
+// A canned patch, for screenshots and snapshot tests
+pub fn draw_demo_ui<S: Surface>(oled: &mut Display<S>, title: &str) -> Result<(), WaveJumperError> {
+    let demux_dots: Vec<Dot> = get_dot_row(demux_ui_ycoord, dot_ui_size, dot_ui_xpad, 16);
+    let mut muxin_dots: Vec<Dot> = get_dot_row(muxin_ui_ycoord, dot_ui_size, dot_ui_xpad, 16);
+    let demux_dot: Dot = Dot { lv: DotLevel::High, ..demux_dots[3] };
+    let mut links: Vec<Link> = Vec::new();
+    for k in [0, 9, 12] {
+        muxin_dots[k].lv = DotLevel::High;
+        links.push(Link { a: demux_dot, b: muxin_dots[k] });
+    }
+    // Four decaying hits, like a drum loop
+    let peaks: Vec<u8> = (0..128).map(|k| 255 - (k % 32) * 7).map(|p| p as u8).collect();
+    redraw_ui(oled, title, Strip::Wave(&peaks, 3.5 / 16.0), &demux_dot, &muxin_dots, &links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::FrameBuffer;
+
+    use std::path::PathBuf;
+
+    // Regenerate with WAVE_JUMPER_BLESS=1 cargo test
+    fn assert_snapshot(name: &str, frame: &FrameBuffer) {
+        let path: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("snapshots").join(name);
+        if std::env::var_os("WAVE_JUMPER_BLESS").is_some() {
+            frame.save(&path).unwrap();
+        }
+        let golden: Vec<u8> = std::fs::read(&path).expect("missing snapshot, run with WAVE_JUMPER_BLESS=1");
+        assert!(golden == frame.to_pbm(), "{} differs from its snapshot", name);
+    }
+
+    #[test]
+    fn demo_ui_matches_snapshot() {
+        let mut oled = Display::new(FrameBuffer::oled());
+        draw_demo_ui(&mut oled, "./assets/arp.wav").unwrap();
+        assert_snapshot("demo_ui.pbm", oled.surface());
+    }
+
+    #[test]
+    fn cleared_dot_leaves_an_outline() {
+        let mut oled = Display::new(FrameBuffer::oled());
+        let mut dot: Dot = get_dot_row(demux_ui_ycoord, dot_ui_size, dot_ui_xpad, 1)[0];
+        fill_dot(&mut oled, &mut dot).unwrap();
+        clear_dot(&mut oled, &mut dot).unwrap();
+        let centre: (u32, u32) = ((dot.x + 3) as u32, (dot.y + 3) as u32);
+        assert!(dot.is_low());
+        assert!(!oled.surface().pixel(centre.0, centre.1));
+        assert!(oled.surface().pixel(dot.x as u32 + 3, dot.y as u32));
+    }
+
+    #[test]
+    fn kits_letter_every_step() {
+        let mut oled = Display::new(FrameBuffer::oled());
+        let letters: Vec<char> = "AABBCCDD".chars().collect();
+        show_kit(&mut oled, &letters, 6).unwrap();
+        let lit = |k: u32| (0..dot_ui_xpad).flat_map(|x| (0..wave_ui_ysize).map(move |y| (x, y)))
+            .filter(|&(x, y)| oled.surface().pixel(wave_ui_xcoord as u32 + k * dot_ui_xpad + x, wave_ui_ycoord as u32 + y))
+            .count();
+        // Past the last step there's no letter
+        assert!((0..6).all(|k| lit(k) > 0));
+        assert_eq!(lit(6), 0);
+    }
+
+    #[test]
+    fn off_screen_drawing_is_an_error() {
+        let mut oled = Display::new(FrameBuffer::oled());
+        assert!(oled.circle(200, 10, 7, None).is_err());
+        assert!(oled.line(0, 0, 5, -1, None).is_err());
+    }
+}